@@ -0,0 +1,294 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, LazyLock, Mutex, OnceLock, Weak},
+    time::SystemTime,
+};
+
+use dht::async_dht::AsyncDht;
+use ed25519_dalek::SigningKey;
+use futures_lite::StreamExt;
+use iroh::{
+    Endpoint, EndpointAddr, EndpointId, TransportAddr,
+    address_lookup::{
+        AddressLookup, EndpointData, EndpointInfo, Error as AddressLookupError, Item, MemoryLookup,
+    },
+};
+use n0_future::boxed::BoxStream;
+use serde::{Deserialize, Serialize};
+
+/// BEP44 salt of addressing records, keeps them apart from other mutable items
+/// signed with the endpoint key.
+pub(crate) const ADDR_SALT: &[u8] = b"/iroh/topic-discovery/addr/v1";
+
+/// Provenance reported to iroh for addresses resolved from the DHT.
+const ADDR_PROVENANCE: &str = "iroh-topic-tracker-dht";
+
+/// Max size of a BEP44 value.
+const MAX_ADDR_VALUE: usize = 1000;
+
+/// Address books registered with endpoints, by endpoint id.
+///
+/// iroh can't unregister an address lookup, so all subscriptions of an endpoint
+/// share one. The registered lookup holds the only strong reference, the entry
+/// is replaced once the endpoint dropped its lookup services.
+static ADDR_BOOKS: LazyLock<Mutex<HashMap<EndpointId, Registration>>> =
+    LazyLock::new(Default::default);
+
+/// Shared lookup of an endpoint and a handle that dies with the registered lookup.
+type Registration = (MemoryLookup, Weak<()>);
+
+/// Addresses learned outside of iroh's default address lookup (LAN announcements,
+/// bootstrap addresses, DHT records), made available to the endpoint.
+#[derive(Debug, Clone)]
+pub(crate) struct AddrBook {
+    lookup: MemoryLookup,
+}
+
+impl AddrBook {
+    /// The endpoint's book, registering an in-memory address lookup on first use.
+    pub fn for_endpoint(endpoint: &Endpoint) -> anyhow::Result<Self> {
+        let mut books = ADDR_BOOKS.lock().expect("poisoned");
+        books.retain(|_, (_, registration)| registration.strong_count() > 0);
+        if let Some((lookup, _)) = books.get(&endpoint.id()) {
+            return Ok(Self {
+                lookup: lookup.clone(),
+            });
+        }
+
+        let lookup = MemoryLookup::new();
+        let registration = Arc::new(());
+        books.insert(
+            endpoint.id(),
+            (lookup.clone(), Arc::downgrade(&registration)),
+        );
+        endpoint.address_lookup()?.add(RegisteredLookup {
+            lookup: lookup.clone(),
+            _registration: registration,
+        });
+        Ok(Self { lookup })
+    }
+
+    pub fn add(&self, addr: EndpointAddr) {
+        if addr.is_empty() {
+            return;
+        }
+        tracing::debug!("addr_book: adding addresses for {}", addr.id.fmt_short());
+        self.lookup.add_endpoint_info(addr);
+    }
+}
+
+/// The lookup registered with the endpoint, alive as long as the endpoint's lookup services.
+#[derive(Debug)]
+struct RegisteredLookup {
+    lookup: MemoryLookup,
+    _registration: Arc<()>,
+}
+
+impl AddressLookup for RegisteredLookup {
+    fn resolve(
+        &self,
+        endpoint_id: EndpointId,
+    ) -> Option<BoxStream<Result<Item, AddressLookupError>>> {
+        self.lookup.resolve(endpoint_id)
+    }
+}
+
+/// Direct addresses of our endpoint, as announced to other nodes.
+pub(crate) fn own_direct_addrs(endpoint: &Endpoint) -> Vec<SocketAddr> {
+    endpoint.addr().ip_addrs().copied().collect()
+}
+
+/// Value of the addressing record, the endpoint id is the BEP44 key.
+#[derive(Debug, Serialize, Deserialize)]
+struct AddrRecord {
+    addrs: Vec<TransportAddr>,
+}
+
+/// Encodes relay URLs and direct addresses, dropping direct addresses until the record fits.
+fn encode_addr_record(addr: &EndpointAddr) -> anyhow::Result<Vec<u8>> {
+    // relay first so it survives truncation
+    let mut addrs = addr
+        .addrs
+        .iter()
+        .filter(|addr| matches!(addr, TransportAddr::Relay(_)))
+        .chain(
+            addr.addrs
+                .iter()
+                .filter(|addr| matches!(addr, TransportAddr::Ip(_))),
+        )
+        .cloned()
+        .collect::<Vec<_>>();
+    loop {
+        let value = postcard::to_stdvec(&AddrRecord {
+            addrs: addrs.clone(),
+        })?;
+        if value.len() <= MAX_ADDR_VALUE {
+            return Ok(value);
+        }
+        anyhow::ensure!(addrs.pop().is_some(), "addressing record too large");
+    }
+}
+
+/// Publish our relay URL and direct addresses as a BEP44 mutable item.
+pub(crate) async fn publish_addr(
+    dht: &AsyncDht,
+    signing_key: &SigningKey,
+    addr: &EndpointAddr,
+) -> anyhow::Result<()> {
+    let value = encode_addr_record(addr)?;
+    // newer records win, micros since the epoch fit an i64 for a long time
+    let seq = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as i64;
+    let item = dht::MutableItem::new(signing_key, &value, seq, Some(ADDR_SALT));
+    dht.put_mutable(item, None).await?;
+    Ok(())
+}
+
+/// Most recent addressing record of `peer`, None if nobody returned a valid one.
+pub(crate) async fn resolve_addr(dht: &AsyncDht, peer: EndpointId) -> Option<EndpointAddr> {
+    let item = dht
+        .get_mutable_most_recent(peer.as_bytes(), Some(ADDR_SALT))
+        .await?;
+    let record: AddrRecord = postcard::from_bytes(item.value()).ok()?;
+    Some(EndpointAddr::from_parts(peer, record.addrs))
+}
+
+/// DHT client of a topic subscription, unset until it bootstrapped.
+///
+/// Holds the latest addressing record published before that and publishes it
+/// once the DHT is set.
+#[derive(Debug)]
+pub(crate) struct DhtCell {
+    dht: OnceLock<Arc<AsyncDht>>,
+    signing_key: SigningKey,
+    /// Latest record waiting for the DHT, the lock also orders it against `set`
+    pending: Mutex<Option<EndpointAddr>>,
+}
+
+impl DhtCell {
+    pub(crate) fn new(signing_key: SigningKey) -> Self {
+        Self {
+            dht: OnceLock::new(),
+            signing_key,
+            pending: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn get(&self) -> Option<Arc<AsyncDht>> {
+        self.dht.get().cloned()
+    }
+
+    /// Set the bootstrapped DHT and publish the record that waited for it.
+    pub(crate) fn set(&self, dht: Arc<AsyncDht>) {
+        let mut pending = self.pending.lock().expect("poisoned");
+        if self.dht.set(dht.clone()).is_err() {
+            return;
+        }
+        if let Some(addr) = pending.take() {
+            tracing::debug!("dht_address_lookup: DHT ready, publishing held record");
+            spawn_publish(dht, self.signing_key.clone(), addr);
+        }
+    }
+
+    /// Publish `addr` now, or hold it until the DHT is set, replacing any older one.
+    fn publish(&self, addr: EndpointAddr) {
+        let mut pending = self.pending.lock().expect("poisoned");
+        match self.dht.get() {
+            Some(dht) => spawn_publish(dht.clone(), self.signing_key.clone(), addr),
+            None => {
+                tracing::debug!("dht_address_lookup: DHT not ready, holding record");
+                *pending = Some(addr);
+            }
+        }
+    }
+}
+
+fn spawn_publish(dht: Arc<AsyncDht>, signing_key: SigningKey, addr: EndpointAddr) {
+    tokio::spawn(async move {
+        if let Err(e) = publish_addr(&dht, &signing_key, &addr).await {
+            tracing::warn!("dht_address_lookup: publish failed: {e}");
+        }
+    });
+}
+
+/// iroh address lookup backed by the DHT of a topic subscription.
+///
+/// Publishes our addressing record and resolves other endpoints through the same
+/// DHT client as topic discovery, see `TopicDiscoveryHandle::address_lookup`.
+/// Register it with `endpoint.address_lookup()?.add(lookup)`. Records published before the
+/// DHT bootstrapped are held and published once it did.
+#[derive(Debug, Clone)]
+pub struct DhtAddressLookup {
+    dht: Arc<DhtCell>,
+}
+
+impl DhtAddressLookup {
+    pub(crate) fn new(dht: Arc<DhtCell>) -> Self {
+        Self { dht }
+    }
+}
+
+impl AddressLookup for DhtAddressLookup {
+    fn publish(&self, data: &EndpointData) {
+        self.dht.publish(EndpointAddr::from_parts(
+            iroh::PublicKey::from_verifying_key(self.dht.signing_key.verifying_key()),
+            data.addrs().cloned(),
+        ));
+    }
+
+    fn resolve(
+        &self,
+        endpoint_id: EndpointId,
+    ) -> Option<BoxStream<Result<Item, AddressLookupError>>> {
+        let dht = self.dht.get()?;
+        let resolved =
+            futures_lite::stream::once_future(async move { resolve_addr(&dht, endpoint_id).await });
+        Some(
+            resolved
+                .filter_map(|addr| {
+                    addr.map(|addr| Ok(Item::new(EndpointInfo::from(addr), ADDR_PROVENANCE, None)))
+                })
+                .boxed(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::test_utils::testnet_dht;
+
+    #[tokio::test]
+    async fn lookup_publishes_the_latest_record_once_the_dht_is_set() -> anyhow::Result<()> {
+        let (_testnet, dht) = testnet_dht().await?;
+
+        let signing_key = SigningKey::from_bytes(&rand::random());
+        let id = EndpointId::from_bytes(signing_key.verifying_key().as_bytes())?;
+        let cell = Arc::new(DhtCell::new(signing_key));
+        let lookup = DhtAddressLookup::new(cell.clone());
+
+        let old = TransportAddr::Ip("10.0.0.1:4433".parse()?);
+        let latest = TransportAddr::Ip("10.0.0.2:4433".parse()?);
+        lookup.publish(&EndpointData::new(vec![old]));
+        lookup.publish(&EndpointData::new(vec![latest.clone()]));
+        assert!(lookup.resolve(id).is_none());
+
+        cell.set(Arc::new(dht.clone()));
+        let resolved = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                if let Some(addr) = resolve_addr(&dht, id).await {
+                    return addr;
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await?;
+        assert_eq!(resolved.addrs.into_iter().collect::<Vec<_>>(), vec![latest]);
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+use dht::async_dht::AsyncDht;
+use ed25519_dalek::SigningKey;
+use iroh::EndpointId;
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+
+/// Domain separator of the descriptor salt.
+const DESCRIPTOR_DOMAIN: &[u8] = b"/iroh/topic-discovery/descriptor/v1";
+
+/// Max size of a BEP44 value.
+const MAX_DESCRIPTOR_VALUE: usize = 1000;
+
+/// What a topic is and who controls it, published by the topic owner.
+///
+/// Stored as a BEP44 mutable item signed by the owner, salted with a hash of the
+/// topic's DHT infohash, so each topic of an owner has its own descriptor.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TopicDescriptor {
+    pub name: String,
+    pub description: String,
+    pub owner: EndpointId,
+    pub flags: TopicFlags,
+    /// Increased by the owner on every change, newer versions replace older ones
+    pub version: u64,
+}
+
+/// Policy flags announced in a `TopicDescriptor`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TopicFlags {
+    /// Only holders of a membership capability issued by the owner may join,
+    /// nodes without a `Membership` don't dial any candidates
+    pub invite_only: bool,
+    /// Leading zero bits of the `ProofOfWork` required to join, 0 if none
+    pub pow_difficulty: u8,
+}
+
+impl TopicDescriptor {
+    pub fn new(name: impl Into<String>, owner: EndpointId) -> Self {
+        Self {
+            name: name.into(),
+            description: String::new(),
+            owner,
+            flags: TopicFlags::default(),
+            version: 0,
+        }
+    }
+
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.description = description.into();
+        self
+    }
+
+    pub fn with_flags(mut self, flags: TopicFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    pub fn with_version(mut self, version: u64) -> Self {
+        self.version = version;
+        self
+    }
+}
+
+/// BEP44 salt of the descriptor of the topic with `infohash`.
+pub(crate) fn descriptor_salt(infohash: &[u8; 20]) -> [u8; 32] {
+    let mut hasher = sha2::Sha512::new();
+    hasher.update(DESCRIPTOR_DOMAIN);
+    hasher.update(infohash);
+    hasher.finalize()[..32].try_into().expect("hashing failed")
+}
+
+pub(crate) async fn publish_descriptor(
+    dht: &AsyncDht,
+    signing_key: &SigningKey,
+    infohash: &[u8; 20],
+    descriptor: &TopicDescriptor,
+) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        descriptor.owner.as_bytes() == signing_key.verifying_key().as_bytes(),
+        "descriptor owner doesn't match our key"
+    );
+    let value = postcard::to_stdvec(descriptor)?;
+    anyhow::ensure!(
+        value.len() <= MAX_DESCRIPTOR_VALUE,
+        "descriptor too large ({} bytes)",
+        value.len()
+    );
+    let seq = i64::try_from(descriptor.version)?;
+    let salt = descriptor_salt(infohash);
+    let item = dht::MutableItem::new(signing_key, &value, seq, Some(&salt));
+    dht.put_mutable(item, None).await?;
+    Ok(())
+}
+
+/// Most recent descriptor signed by `owner`, None if none was published.
+///
+/// The BEP44 signature is checked by the DHT client, we check the descriptor
+/// names the same owner and version as the signed item.
+pub(crate) async fn fetch_descriptor(
+    dht: &AsyncDht,
+    owner: EndpointId,
+    infohash: &[u8; 20],
+) -> anyhow::Result<Option<TopicDescriptor>> {
+    let salt = descriptor_salt(infohash);
+    let Some(item) = dht
+        .get_mutable_most_recent(owner.as_bytes(), Some(&salt))
+        .await
+    else {
+        return Ok(None);
+    };
+    let descriptor: TopicDescriptor = postcard::from_bytes(item.value())?;
+    anyhow::ensure!(descriptor.owner == owner, "descriptor names another owner");
+    anyhow::ensure!(
+        i64::try_from(descriptor.version).ok() == Some(item.seq()),
+        "descriptor version doesn't match the signed sequence number"
+    );
+    Ok(Some(descriptor))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::testnet_dht;
+
+    #[tokio::test]
+    async fn descriptor_roundtrips_through_the_dht() -> anyhow::Result<()> {
+        let (_testnet, dht) = testnet_dht().await?;
+
+        let owner_key = SigningKey::from_bytes(&rand::random());
+        let owner = EndpointId::from_bytes(owner_key.verifying_key().as_bytes())?;
+        let infohash = [7u8; 20];
+        let descriptor = TopicDescriptor::new("chat", owner)
+            .with_description("team chat")
+            .with_flags(TopicFlags {
+                invite_only: true,
+                pow_difficulty: 8,
+            })
+            .with_version(3);
+
+        assert_eq!(fetch_descriptor(&dht, owner, &infohash).await?, None);
+        publish_descriptor(&dht, &owner_key, &infohash, &descriptor).await?;
+        assert_eq!(
+            fetch_descriptor(&dht, owner, &infohash).await?,
+            Some(descriptor.clone())
+        );
+        // other topics of the same owner are salted apart
+        assert_eq!(fetch_descriptor(&dht, owner, &[8u8; 20]).await?, None);
+
+        let stranger = SigningKey::from_bytes(&rand::random());
+        assert!(
+            publish_descriptor(&dht, &stranger, &infohash, &descriptor)
+                .await
+                .is_err()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,159 @@
+use std::time::{Instant, SystemTime};
+
+use serde::Serialize;
+
+use crate::{
+    health::DhtHealth, health::LookupDiagnostics, metrics::DiscoveryMetrics,
+    population::PopulationEstimate, source::SourceKind,
+};
+
+/// Snapshot of everything we know about one topic subscription, for bug reports.
+///
+/// Timestamps are unix milliseconds, ages are seconds before the report was taken,
+/// see `TopicDiscoveryHandle::diagnostics`.
+#[derive(Debug, Clone, Serialize)]
+pub struct DiagnosticsReport {
+    pub phase: DiscoveryPhase,
+    pub dht_bootstrapped: bool,
+    pub routing_table_size: usize,
+    pub dht_health: DhtHealth,
+    /// None until the first self-lookup of our announcement finished
+    pub announcement_visible: Option<bool>,
+    pub announcement_last_verified_secs_ago: Option<u64>,
+    pub last_announce: Option<AnnounceReport>,
+    pub last_lookup: Option<LookupReport>,
+    pub attempted_peers: Vec<PeerReport>,
+    /// Once connected neighbors that currently have an active path
+    pub live_neighbors: Vec<String>,
+    pub population: PopulationEstimate,
+    pub metrics: DiscoveryMetrics,
+}
+
+impl DiagnosticsReport {
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum DiscoveryPhase {
+    /// No neighbor connected yet, discovery runs in aggressive mode
+    NoPeers,
+    /// Within `first_connected_duration` after the first neighbor connected
+    FirstConnected,
+    Connected,
+    Stopped,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum AnnounceResult {
+    Ok,
+    Failed,
+    TimedOut,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AnnounceReport {
+    pub result: AnnounceResult,
+    pub error: Option<String>,
+    pub at_unix_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LookupReport {
+    #[serde(flatten)]
+    pub lookup: LookupDiagnostics,
+    pub at_unix_ms: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum PeerOutcome {
+    Pending,
+    Connected,
+    TimedOut,
+    /// Discovery stopped while connecting
+    Aborted,
+    /// No valid membership capability in the pre-join handshake
+    Rejected,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerReport {
+    pub peer: String,
+    /// Source that found the peer
+    pub source: Option<SourceKind>,
+    pub attempts: u32,
+    pub outcome: PeerOutcome,
+    pub last_attempt_unix_ms: u64,
+}
+
+/// Attempt history of one peer, kept in the discovery state.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PeerAttempts {
+    pub attempts: u32,
+    pub outcome: PeerOutcome,
+    pub last_attempt: SystemTime,
+}
+
+pub(crate) fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+pub(crate) fn secs_ago(instant: Instant) -> u64 {
+    instant.elapsed().as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_json_flattens_the_lookup_and_names_enums() -> anyhow::Result<()> {
+        let report = DiagnosticsReport {
+            phase: DiscoveryPhase::NoPeers,
+            dht_bootstrapped: true,
+            routing_table_size: 12,
+            dht_health: DhtHealth::Empty,
+            announcement_visible: None,
+            announcement_last_verified_secs_ago: None,
+            last_announce: Some(AnnounceReport {
+                result: AnnounceResult::Ok,
+                error: None,
+                at_unix_ms: 1_000,
+            }),
+            last_lookup: Some(LookupReport {
+                lookup: LookupDiagnostics {
+                    records: 3,
+                    stale_records: 1,
+                    ..Default::default()
+                },
+                at_unix_ms: 2_000,
+            }),
+            attempted_peers: vec![PeerReport {
+                peer: "peer".to_string(),
+                source: Some(SourceKind::Dht),
+                attempts: 2,
+                outcome: PeerOutcome::TimedOut,
+                last_attempt_unix_ms: 3_000,
+            }],
+            live_neighbors: Vec::new(),
+            population: PopulationEstimate::default(),
+            metrics: DiscoveryMetrics::default(),
+        };
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()?)?;
+        assert_eq!(json["phase"], "NoPeers");
+        assert_eq!(json["dht_health"], "Empty");
+        assert_eq!(json["routing_table_size"], 12);
+        assert!(json["announcement_visible"].is_null());
+        assert_eq!(json["last_announce"]["result"], "Ok");
+        assert_eq!(json["last_lookup"]["records"], 3);
+        assert_eq!(json["last_lookup"]["stale_records"], 1);
+        assert_eq!(json["last_lookup"]["at_unix_ms"], 2_000);
+        assert_eq!(json["attempted_peers"][0]["source"], "Dht");
+        assert_eq!(json["attempted_peers"][0]["outcome"], "TimedOut");
+        Ok(())
+    }
+}
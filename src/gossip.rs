@@ -1,21 +1,47 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     sync::{
         Arc,
         atomic::{AtomicBool, Ordering},
     },
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
 };
 
 use dht::async_dht::AsyncDht;
 use ed25519_dalek::SigningKey;
 use futures_lite::StreamExt;
-use iroh::{Endpoint, EndpointId};
-use iroh_gossip::api::{GossipReceiver, GossipSender};
+use iroh::{Endpoint, EndpointAddr, EndpointId};
+use iroh_gossip::{
+    api::{GossipReceiver, GossipSender},
+    proto::TopicId,
+};
 use n0_future::time;
-use n0_watcher::Watchable;
-use sha2::Digest;
-use tokio::sync::Mutex;
+use n0_watcher::{Watchable, Watcher};
+use tokio::sync::{Mutex, mpsc};
+
+use crate::{
+    addr::{AddrBook, DhtAddressLookup, DhtCell, own_direct_addrs, publish_addr, resolve_addr},
+    descriptor::{TopicDescriptor, fetch_descriptor, publish_descriptor},
+    diagnostics::{
+        AnnounceReport, AnnounceResult, DiagnosticsReport, DiscoveryPhase, LookupReport,
+        PeerAttempts, PeerOutcome, PeerReport, secs_ago, unix_millis,
+    },
+    health::{DhtHealth, LookupDiagnostics},
+    lan::{LanDiscovery, LanDiscoveryConfig},
+    membership::{Membership, fetch_revocations, publish_revocations},
+    metrics::{DiscoveryMetrics, LookupCounts, MetricsCounters},
+    partition::PartitionHealing,
+    policy::{AnnounceMode, RecordFreshness, StalenessPolicy, Timings},
+    population::{PopulationEstimate, PopulationEstimator},
+    pow::{fetch_proof, publish_proof},
+    schedule::{AdaptiveScheduler, DiscoverySchedule},
+    selector::{NewestSelector, PeerCandidate, PeerSelection, PeerSelector},
+    source::{
+        DialLimiter, DiscoveredPeer, DiscoverySource, SourceKind, SourcePolicy, StaticSource,
+    },
+    ticket::TopicTicket,
+    topic::{TopicScheme, shard_for_key, shard_infohash, topic_dht_id},
+};
 
 #[derive(Debug, Clone)]
 pub struct TopicDiscoveryConfig {
@@ -40,6 +66,42 @@ pub struct TopicDiscoveryConfig {
     max_peers_per_round: Option<usize>,
     /// DHT initialization retry count if None infinite retries
     dht_retries: Option<usize>,
+    /// Topic derivation (default: v2 without namespace)
+    topic_scheme: TopicScheme,
+    /// Additional schemes we announce and look up under, e.g. the previous scheme during a migration
+    compat_topic_schemes: Vec<TopicScheme>,
+    /// Timeouts, retry delays and jitter for DHT and connector operations
+    timings: Timings,
+    /// Which discovered peers get dialed (default: newest announcements first)
+    peer_selector: Arc<dyn PeerSelector>,
+    /// Max age and clock skew accepted for signed peer records
+    staleness_policy: StalenessPolicy,
+    /// How often to look up our own announcement between announces, None disables (default: 60s)
+    self_check_interval: Option<Duration>,
+    /// Consecutive degraded lookups before the bootstrap nodes and other sources are polled again (default: 3)
+    fallback_after_lookups: usize,
+    /// Multicast discovery on the local network, None disables (default: None)
+    lan_discovery: Option<LanDiscoveryConfig>,
+    /// Extra candidate sources such as a peer cache or peer exchange
+    discovery_sources: Vec<Arc<dyn DiscoverySource>>,
+    /// Overrides of `SourceKind::default_policy`
+    source_policies: HashMap<SourceKind, SourcePolicy>,
+    /// Publish our addresses to the DHT and resolve discovered peers through it (default: false)
+    dht_addressing: bool,
+    /// Owner whose signed `TopicDescriptor` is fetched, None skips the lookup (default: None)
+    topic_owner: Option<EndpointId>,
+    /// Invite-only mode, peers are dialed after a capability handshake (default: None)
+    membership: Option<Membership>,
+    /// Infohashes per topic, all members must agree on it (default: 1, unsharded)
+    shards: u16,
+    /// Shards sampled per lookup, including our own (default: 3)
+    lookup_shards: usize,
+    /// Whether every announce round writes to the DHT (default: always)
+    announce_mode: AnnounceMode,
+    /// Pause between lookups once connected (default: fixed intervals)
+    discovery_schedule: DiscoverySchedule,
+    /// Random record sampling and member digest checks against split swarms (default: None)
+    partition_healing: Option<PartitionHealing>,
 }
 
 pub struct ConfigBuilder(TopicDiscoveryConfig);
@@ -90,6 +152,127 @@ impl ConfigBuilder {
         self
     }
 
+    /// Fold an application namespace into the current topic scheme.
+    pub fn namespace(mut self, namespace: impl Into<Vec<u8>>) -> Self {
+        self.0.topic_scheme = self.0.topic_scheme.with_namespace(namespace);
+        self
+    }
+
+    pub fn topic_scheme(mut self, scheme: TopicScheme) -> Self {
+        self.0.topic_scheme = scheme;
+        self
+    }
+
+    /// Also announce and look up under these schemes (compatibility mode).
+    ///
+    /// Each compat scheme gets its own gossip subscription, peers found under its
+    /// infohash are joined there instead of the current topic, see
+    /// `TopicDiscoveryHandle::take_compat_topics`.
+    pub fn compat_topic_schemes(mut self, schemes: Vec<TopicScheme>) -> Self {
+        self.0.compat_topic_schemes = schemes;
+        self
+    }
+
+    pub fn timings(mut self, timings: Timings) -> Self {
+        self.0.timings = timings;
+        self
+    }
+
+    pub fn peer_selection(mut self, selection: PeerSelection) -> Self {
+        self.0.peer_selector = selection.into_selector();
+        self
+    }
+
+    /// Custom selection strategy, shared by all subscriptions using this config.
+    pub fn peer_selector(mut self, selector: Arc<dyn PeerSelector>) -> Self {
+        self.0.peer_selector = selector;
+        self
+    }
+
+    pub fn staleness_policy(mut self, policy: StalenessPolicy) -> Self {
+        self.0.staleness_policy = policy;
+        self
+    }
+
+    pub fn self_check_interval(mut self, interval: Option<Duration>) -> Self {
+        self.0.self_check_interval = interval;
+        self
+    }
+
+    pub fn fallback_after_lookups(mut self, lookups: usize) -> Self {
+        self.0.fallback_after_lookups = lookups;
+        self
+    }
+
+    /// Enable LAN discovery. The DHT is then initialized in the background so
+    /// subscribing doesn't block on it without internet access.
+    pub fn lan_discovery(mut self, lan: Option<LanDiscoveryConfig>) -> Self {
+        self.0.lan_discovery = lan;
+        self
+    }
+
+    /// Add a source of candidate peers, see `DiscoverySource`.
+    pub fn discovery_source(mut self, source: Arc<dyn DiscoverySource>) -> Self {
+        self.0.discovery_sources.push(source);
+        self
+    }
+
+    /// Priority and dial rate limit for candidates of `kind`.
+    pub fn source_policy(mut self, kind: SourceKind, policy: SourcePolicy) -> Self {
+        self.0.source_policies.insert(kind, policy);
+        self
+    }
+
+    /// Publish our relay URL and direct addresses as a BEP44 mutable item next to
+    /// every announce, and resolve discovered peers' records before dialing them.
+    /// Lets discovery work without n0's DNS address lookup.
+    pub fn dht_addressing(mut self, enabled: bool) -> Self {
+        self.0.dht_addressing = enabled;
+        self
+    }
+
+    /// Fetch and verify the topic descriptor published by `owner`, see
+    /// `TopicDiscoveryHandle::descriptor`.
+    pub fn topic_owner(mut self, owner: Option<EndpointId>) -> Self {
+        self.0.topic_owner = owner;
+        self
+    }
+
+    /// Only join peers that present a valid membership capability, see `Membership`.
+    pub fn membership(mut self, membership: Option<Membership>) -> Self {
+        self.0.membership = membership;
+        self
+    }
+
+    /// Split the topic into `shards` infohashes for swarms larger than what DHT
+    /// nodes store per infohash. Every node announces into the shard of its key
+    /// prefix, lookups sample `lookup_shards` shards.
+    pub fn shards(mut self, shards: u16) -> Self {
+        self.0.shards = shards.max(1);
+        self
+    }
+
+    pub fn lookup_shards(mut self, lookup_shards: usize) -> Self {
+        self.0.lookup_shards = lookup_shards.max(1);
+        self
+    }
+
+    pub fn announce_mode(mut self, mode: AnnounceMode) -> Self {
+        self.0.announce_mode = mode;
+        self
+    }
+
+    pub fn discovery_schedule(mut self, schedule: DiscoverySchedule) -> Self {
+        self.0.discovery_schedule = schedule;
+        self
+    }
+
+    /// Detect and bridge split swarms every discovery round, see `PartitionHealing`.
+    pub fn partition_healing(mut self, healing: Option<PartitionHealing>) -> Self {
+        self.0.partition_healing = healing;
+        self
+    }
+
     pub fn build(&self) -> TopicDiscoveryConfig {
         self.0.clone()
     }
@@ -109,6 +292,24 @@ impl TopicDiscoveryConfig {
             retry_interval: Duration::from_secs(300),
             max_peers_per_round: Some(5),
             dht_retries: None,
+            topic_scheme: TopicScheme::default(),
+            compat_topic_schemes: Vec::new(),
+            timings: Timings::default(),
+            peer_selector: Arc::new(NewestSelector),
+            staleness_policy: StalenessPolicy::default(),
+            self_check_interval: Some(Duration::from_secs(60)),
+            fallback_after_lookups: 3,
+            lan_discovery: None,
+            discovery_sources: Vec::new(),
+            source_policies: HashMap::new(),
+            dht_addressing: false,
+            topic_owner: None,
+            membership: None,
+            shards: 1,
+            lookup_shards: 3,
+            announce_mode: AnnounceMode::Always,
+            discovery_schedule: DiscoverySchedule::Fixed,
+            partition_healing: None,
         })
     }
 
@@ -139,8 +340,173 @@ impl TopicDiscoveryConfig {
     pub fn dht_retries(&self) -> Option<usize> {
         self.dht_retries
     }
+
+    pub fn topic_scheme(&self) -> &TopicScheme {
+        &self.topic_scheme
+    }
+
+    pub fn compat_topic_schemes(&self) -> &[TopicScheme] {
+        &self.compat_topic_schemes
+    }
+
+    pub fn timings(&self) -> &Timings {
+        &self.timings
+    }
+
+    pub fn peer_selector(&self) -> &Arc<dyn PeerSelector> {
+        &self.peer_selector
+    }
+
+    pub fn staleness_policy(&self) -> &StalenessPolicy {
+        &self.staleness_policy
+    }
+
+    pub fn self_check_interval(&self) -> Option<Duration> {
+        self.self_check_interval
+    }
+
+    pub fn fallback_after_lookups(&self) -> usize {
+        self.fallback_after_lookups
+    }
+
+    pub fn lan_discovery(&self) -> Option<&LanDiscoveryConfig> {
+        self.lan_discovery.as_ref()
+    }
+
+    pub fn discovery_sources(&self) -> &[Arc<dyn DiscoverySource>] {
+        &self.discovery_sources
+    }
+
+    pub fn dht_addressing(&self) -> bool {
+        self.dht_addressing
+    }
+
+    pub fn topic_owner(&self) -> Option<EndpointId> {
+        self.topic_owner
+    }
+
+    pub fn membership(&self) -> Option<&Membership> {
+        self.membership.as_ref()
+    }
+
+    pub fn shards(&self) -> u16 {
+        self.shards
+    }
+
+    pub fn lookup_shards(&self) -> usize {
+        self.lookup_shards
+    }
+
+    pub fn announce_mode(&self) -> AnnounceMode {
+        self.announce_mode
+    }
+
+    pub fn discovery_schedule(&self) -> DiscoverySchedule {
+        self.discovery_schedule
+    }
+
+    pub fn partition_healing(&self) -> Option<&PartitionHealing> {
+        self.partition_healing.as_ref()
+    }
+
+    /// Shard of our key.
+    pub fn own_shard(&self) -> u16 {
+        shard_for_key(self.signing_key.verifying_key().as_bytes(), self.shards)
+    }
+
+    /// Our shard of every topic infohash.
+    fn announce_shard_ids(&self, ids: &[dht::Id]) -> anyhow::Result<Vec<dht::Id>> {
+        let own_shard = self.own_shard();
+        ids.iter()
+            .map(|id| {
+                Ok(dht::Id::from_bytes(shard_infohash(
+                    id.as_bytes(),
+                    own_shard,
+                    self.shards,
+                ))?)
+            })
+            .collect()
+    }
+
+    /// Our shard plus randomly sampled other shards of every topic infohash,
+    /// returns the infohashes and the sampled shards.
+    fn lookup_shard_ids(&self, ids: &[dht::Id]) -> anyhow::Result<(Vec<dht::Id>, Vec<u16>)> {
+        let own_shard = self.own_shard();
+        let mut shards = (0..self.shards)
+            .filter(|shard| *shard != own_shard)
+            .collect::<Vec<_>>();
+        shards.sort_by_cached_key(|_| rand::random::<u64>());
+        shards.truncate(self.lookup_shards.saturating_sub(1));
+        shards.insert(0, own_shard);
+
+        let mut shard_ids = Vec::with_capacity(ids.len() * shards.len());
+        for id in ids {
+            for shard in &shards {
+                shard_ids.push(dht::Id::from_bytes(shard_infohash(
+                    id.as_bytes(),
+                    *shard,
+                    self.shards,
+                ))?);
+            }
+        }
+        Ok((shard_ids, shards))
+    }
+
+    pub fn source_policy(&self, kind: &SourceKind) -> SourcePolicy {
+        self.source_policies
+            .get(kind)
+            .copied()
+            .unwrap_or_else(|| kind.default_policy())
+    }
+
+    /// Max age of a peer record we still dial.
+    pub fn max_record_age(&self) -> Duration {
+        self.staleness_policy
+            .max_age
+            .unwrap_or(self.announce_interval + self.timings.staleness_buffer)
+    }
+
+    /// Topic hashes: current scheme first, then compat schemes deriving other topics.
+    fn topic_hashes(&self, topic_bytes: &[u8]) -> Vec<[u8; 32]> {
+        let mut hashes = Vec::with_capacity(1 + self.compat_topic_schemes.len());
+        for scheme in std::iter::once(&self.topic_scheme).chain(&self.compat_topic_schemes) {
+            let hash = scheme.topic_hash_32(topic_bytes);
+            if !hashes.contains(&hash) {
+                hashes.push(hash);
+            }
+        }
+        hashes
+    }
+}
+
+/// Whether our own signed peer record could be found on the DHT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnnouncementStatus {
+    /// No self-check finished yet
+    Unknown,
+    /// Our record was found with a fresh timestamp under every infohash
+    Visible { last_verified: Instant },
+    /// The latest self-check did not find a fresh record, a re-announce was triggered
+    NotVisible {
+        last_checked: Instant,
+        last_verified: Option<Instant>,
+    },
+}
+
+impl AnnouncementStatus {
+    pub fn last_verified(&self) -> Option<Instant> {
+        match self {
+            AnnouncementStatus::Unknown => None,
+            AnnouncementStatus::Visible { last_verified } => Some(*last_verified),
+            AnnouncementStatus::NotVisible { last_verified, .. } => *last_verified,
+        }
+    }
 }
 
+/// A candidate peer, its source and the index of the gossip topic to join it
+/// into: 0 for the current scheme, `i` for the `i`th compat topic.
+type Candidate = (SourceKind, DiscoveredPeer, usize);
+
 #[derive(Debug, Clone)]
 struct DiscoveryState {
     /// Number of peers we've successfully joined to gossip
@@ -153,23 +519,96 @@ struct DiscoveryState {
     retry_interval: Duration,
     /// First connection timestamp to switch discovery intervals
     first_connected_timestamp: Watchable<Option<Instant>>,
+    /// Number of once connected neighbors, for waiting without polling
+    connected_count: Watchable<usize>,
+    /// Announce, lookup and connector counters
+    metrics: Arc<MetricsCounters>,
+    /// When discovery started, for time to first peer
+    started_at: Instant,
+    /// Result of the latest self-lookup of our announcement
+    announcement_status: Watchable<AnnouncementStatus>,
+    /// Whether the latest announce was accepted by at least one DHT node, None before the first
+    announce_accepted: Watchable<Option<bool>>,
+    /// Signed peer support of the DHT, updated after every lookup
+    dht_health: Watchable<DhtHealth>,
+    /// Node and record counts of the latest lookup
+    last_lookup: Arc<Mutex<Option<LookupReport>>>,
+    /// Result of the latest announce
+    last_announce: Arc<Mutex<Option<AnnounceReport>>>,
+    /// Attempt count and latest outcome per dialed peer
+    peer_attempts: Arc<Mutex<HashMap<EndpointId, PeerAttempts>>>,
+    /// Addresses learned from LAN announcements and other sources
+    addr_book: AddrBook,
+    /// Candidates of all sources, dialed by the dispatch task
+    candidates: mpsc::UnboundedSender<Candidate>,
+    /// Source of every dialed peer
+    peer_sources: Arc<Mutex<HashMap<EndpointId, (SourceKind, DiscoveredPeer)>>>,
+    /// Latest verified descriptor of `topic_owner`
+    descriptor: Watchable<Option<TopicDescriptor>>,
+    /// Updated after every lookup
+    population: Watchable<PopulationEstimate>,
+    /// Bootstrap nodes of the subscription, dialed again while the DHT is degraded
+    bootstrap_nodes: Vec<EndpointId>,
+    /// Bumped while the DHT is degraded, finished sources are polled again
+    fallback_rounds: Watchable<u64>,
 }
 
 impl DiscoveryState {
-    fn new(retry_interval: Duration) -> Arc<Self> {
-        Arc::new(Self {
+    fn new(
+        config: &TopicDiscoveryConfig,
+        bootstrap_nodes: Vec<EndpointId>,
+        addr_book: AddrBook,
+    ) -> (Arc<Self>, mpsc::UnboundedReceiver<Candidate>) {
+        let (candidates, candidates_rx) = mpsc::unbounded_channel();
+        let state = Arc::new(Self {
             once_connected_neighbors: Arc::new(Mutex::new(HashSet::new())),
             stopped: Arc::new(AtomicBool::new(false)),
             attempted: Arc::new(Mutex::new(HashMap::new())),
-            retry_interval,
+            retry_interval: config.retry_interval,
             first_connected_timestamp: Watchable::new(None),
-        })
+            connected_count: Watchable::new(0),
+            metrics: Arc::new(MetricsCounters::default()),
+            started_at: Instant::now(),
+            announcement_status: Watchable::new(AnnouncementStatus::Unknown),
+            announce_accepted: Watchable::new(None),
+            dht_health: Watchable::new(DhtHealth::Unknown),
+            last_lookup: Arc::new(Mutex::new(None)),
+            last_announce: Arc::new(Mutex::new(None)),
+            peer_attempts: Arc::new(Mutex::new(HashMap::new())),
+            addr_book,
+            candidates,
+            peer_sources: Arc::new(Mutex::new(HashMap::new())),
+            descriptor: Watchable::new(None),
+            population: Watchable::new(PopulationEstimate::default()),
+            bootstrap_nodes,
+            fallback_rounds: Watchable::new(0),
+        });
+        (state, candidates_rx)
+    }
+
+    /// Hand a candidate to the dispatch task.
+    fn submit(&self, kind: SourceKind, found: DiscoveredPeer) {
+        self.submit_to_topic(kind, found, 0);
+    }
+
+    fn submit_to_topic(&self, kind: SourceKind, found: DiscoveredPeer, topic: usize) {
+        let _ = self.candidates.send((kind, found, topic));
     }
 
     fn stop(&self) {
         self.stopped.store(true, Ordering::Relaxed);
     }
 
+    /// True if the owner's descriptor marks the topic invite-only and we have no
+    /// `Membership` to tell members from strangers.
+    fn refuses_candidates(&self, config: &TopicDiscoveryConfig) -> bool {
+        config.membership.is_none()
+            && self
+                .descriptor
+                .get()
+                .is_some_and(|descriptor| descriptor.flags.invite_only)
+    }
+
     fn is_stopped(&self) -> bool {
         self.stopped.load(Ordering::Relaxed)
     }
@@ -189,6 +628,33 @@ impl DiscoveryState {
         self.once_connected_neighbors.clone()
     }
 
+    async fn add_neighbor(&self, peer: EndpointId) {
+        let mut guard = self.once_connected_neighbors.lock().await;
+        guard.insert(peer);
+        self.connected_count.set(guard.len()).ok();
+    }
+
+    async fn is_neighbor(&self, peer: &EndpointId) -> bool {
+        self.once_connected_neighbors.lock().await.contains(peer)
+    }
+
+    /// Resolves once at least `min` neighbors were connected.
+    async fn wait_for_connections(&self, min: usize) {
+        let mut watcher = self.connected_count.watch();
+        while watcher.get() < min {
+            if watcher.updated().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Returns true if we never attempted the peer or the retry interval has elapsed.
+    async fn can_attempt(&self, peer: [u8; 32]) -> bool {
+        let map = self.attempted.lock().await;
+        map.get(&peer)
+            .is_none_or(|last_attempt| last_attempt.elapsed() > self.retry_interval)
+    }
+
     /// Mark a peer as attempted. Returns true if we should try to connect
     /// (either new peer, or retry interval has elapsed).
     async fn should_attempt(&self, peer: [u8; 32]) -> bool {
@@ -214,6 +680,88 @@ impl DiscoveryState {
         map.remove(&peer);
     }
 
+    fn set_announcement_visible(&self, visible: bool) {
+        let now = Instant::now();
+        let status = if visible {
+            AnnouncementStatus::Visible { last_verified: now }
+        } else {
+            AnnouncementStatus::NotVisible {
+                last_checked: now,
+                last_verified: self.announcement_status.get().last_verified(),
+            }
+        };
+        self.announcement_status.set(status).ok();
+    }
+
+    async fn lookup_finished(&self, lookup: LookupDiagnostics) -> DhtHealth {
+        let health = DhtHealth::classify(&lookup, self.announce_accepted.get());
+        *self.last_lookup.lock().await = Some(LookupReport {
+            lookup,
+            at_unix_ms: unix_millis(SystemTime::now()),
+        });
+        self.dht_health.set(health).ok();
+        health
+    }
+
+    async fn announce_finished(&self, result: AnnounceResult, error: Option<String>) {
+        self.announce_accepted
+            .set(Some(result == AnnounceResult::Ok))
+            .ok();
+        *self.last_announce.lock().await = Some(AnnounceReport {
+            result,
+            error,
+            at_unix_ms: unix_millis(SystemTime::now()),
+        });
+    }
+
+    async fn record_peer_outcome(&self, peer: EndpointId, outcome: PeerOutcome) {
+        let mut map = self.peer_attempts.lock().await;
+        let entry = map.entry(peer).or_insert(PeerAttempts {
+            attempts: 0,
+            outcome,
+            last_attempt: SystemTime::now(),
+        });
+        if outcome == PeerOutcome::Pending {
+            entry.attempts = entry.attempts.saturating_add(1);
+            entry.last_attempt = SystemTime::now();
+        }
+        entry.outcome = outcome;
+    }
+
+    /// Fresh records of other peers per shard in the last lookup, None before the first.
+    async fn visible_records_per_shard(&self, config: &TopicDiscoveryConfig) -> Option<usize> {
+        let sampled_shards = config.lookup_shards.min(config.shards as usize).max(1);
+        self.last_lookup
+            .lock()
+            .await
+            .as_ref()
+            .map(|report| report.lookup.fresh_records / sampled_shards)
+    }
+
+    /// Once connected neighbors that currently have an active path.
+    async fn live_neighbors(&self, endpoint: &Endpoint) -> HashSet<EndpointId> {
+        let neighbors = self.added_neighbors().lock().await.clone();
+        let mut live = HashSet::new();
+        for peer in neighbors {
+            if has_active_path(endpoint, peer).await {
+                live.insert(peer);
+            }
+        }
+        live
+    }
+
+    fn phase(&self, config: &TopicDiscoveryConfig) -> DiscoveryPhase {
+        if self.is_stopped() {
+            DiscoveryPhase::Stopped
+        } else if self.connected_count.get() == 0 {
+            DiscoveryPhase::NoPeers
+        } else if self.first_connected_phase(config) {
+            DiscoveryPhase::FirstConnected
+        } else {
+            DiscoveryPhase::Connected
+        }
+    }
+
     fn first_connected_timestamp_watcher(&self) -> Watchable<Option<Instant>> {
         self.first_connected_timestamp.clone()
     }
@@ -233,6 +781,13 @@ impl DiscoveryState {
 #[derive(Debug)]
 pub struct TopicDiscoveryHandle {
     state: Arc<DiscoveryState>,
+    /// Set once the DHT bootstrapped, in the background when other sources can find peers
+    dht: Arc<DhtCell>,
+    /// Infohashes we announce under, the first one belongs to the primary topic scheme
+    dht_ids: Vec<dht::Id>,
+    config: TopicDiscoveryConfig,
+    /// Subscriptions of the compat schemes' topics, drained until taken by the application
+    compat_topics: Mutex<Vec<CompatTopic>>,
     _tasks: Vec<tokio::task::JoinHandle<()>>,
 }
 
@@ -245,6 +800,26 @@ impl TopicDiscoveryHandle {
         !self.state.is_stopped()
     }
 
+    /// Gossip subscriptions of the compat schemes' topics, in the order of
+    /// `compat_topic_schemes` and skipping schemes that derive the current topic.
+    ///
+    /// Peers running an old scheme are joined into these topics, read from and
+    /// broadcast to them alongside the current topic during a migration. Until
+    /// taken their events are discarded, so a full channel doesn't stall the
+    /// subscription. Returns an empty list once taken.
+    pub async fn take_compat_topics(&self) -> Vec<(GossipSender, GossipReceiver)> {
+        let compat_topics = std::mem::take(&mut *self.compat_topics.lock().await);
+        let mut taken = Vec::with_capacity(compat_topics.len());
+        for compat in compat_topics {
+            let _ = compat.stop.send(());
+            match compat.drain.await {
+                Ok(receiver) => taken.push((compat.sender, receiver)),
+                Err(e) => tracing::warn!("take_compat_topics: drain task failed: {e}"),
+            }
+        }
+        taken
+    }
+
     pub async fn has_connections(&self) -> bool {
         self.state.has_connections().await
     }
@@ -260,8 +835,218 @@ impl TopicDiscoveryHandle {
         let guard = mtx.lock().await;
         guard.clone()
     }
+
+    /// Resolves once at least `min` neighbors were connected (see `added_connection_count`).
+    pub async fn wait_for_connections(&self, min: usize) {
+        self.state.wait_for_connections(min).await
+    }
+
+    /// Result of the latest lookup of our own announcement.
+    pub fn announcement_status(&self) -> AnnouncementStatus {
+        self.state.announcement_status.get()
+    }
+
+    /// Whether the DHT supports signed peer announcements for this topic.
+    pub fn dht_health(&self) -> DhtHealth {
+        self.state.dht_health.get()
+    }
+
+    /// Responding and supporting node counts of the latest lookup.
+    pub async fn last_lookup(&self) -> Option<LookupDiagnostics> {
+        self.state
+            .last_lookup
+            .lock()
+            .await
+            .as_ref()
+            .map(|report| report.lookup)
+    }
+
+    /// Serializable report of the subscription state, see `DiagnosticsReport::to_json`.
+    pub async fn diagnostics(&self) -> DiagnosticsReport {
+        let routing_table_size = match self.dht.get() {
+            Some(dht) => dht.info().await.routing_table_size(),
+            None => 0,
+        };
+        let announcement_status = self.announcement_status();
+
+        let attempted_peers = {
+            let map = self.state.peer_attempts.lock().await;
+            let sources = self.state.peer_sources.lock().await;
+            map.iter()
+                .map(|(peer, attempts)| PeerReport {
+                    peer: peer.to_string(),
+                    source: sources.get(peer).map(|(kind, _)| kind.clone()),
+                    attempts: attempts.attempts,
+                    outcome: attempts.outcome,
+                    last_attempt_unix_ms: unix_millis(attempts.last_attempt),
+                })
+                .collect()
+        };
+
+        let live_neighbors = self
+            .state
+            .live_neighbors(&self.config.endpoint)
+            .await
+            .iter()
+            .map(|peer| peer.to_string())
+            .collect();
+
+        DiagnosticsReport {
+            phase: self.state.phase(&self.config),
+            dht_bootstrapped: routing_table_size > 0,
+            routing_table_size,
+            dht_health: self.dht_health(),
+            announcement_visible: match announcement_status {
+                AnnouncementStatus::Unknown => None,
+                AnnouncementStatus::Visible { .. } => Some(true),
+                AnnouncementStatus::NotVisible { .. } => Some(false),
+            },
+            announcement_last_verified_secs_ago: announcement_status.last_verified().map(secs_ago),
+            last_announce: self.state.last_announce.lock().await.clone(),
+            last_lookup: self.state.last_lookup.lock().await.clone(),
+            attempted_peers,
+            live_neighbors,
+            population: self.population(),
+            metrics: self.metrics(),
+        }
+    }
+
+    /// iroh address lookup that publishes and resolves addresses through this
+    /// subscription's DHT client.
+    pub fn address_lookup(&self) -> DhtAddressLookup {
+        DhtAddressLookup::new(self.dht.clone())
+    }
+
+    /// Latest verified descriptor of the configured `topic_owner`, None until fetched.
+    pub fn descriptor(&self) -> Option<TopicDescriptor> {
+        self.state.descriptor.get()
+    }
+
+    /// Publish `descriptor` for this topic. Only the owner can publish, the
+    /// descriptor's `owner` must be our signing key.
+    pub async fn publish_descriptor(&self, descriptor: &TopicDescriptor) -> anyhow::Result<()> {
+        let dht = self
+            .dht
+            .get()
+            .ok_or_else(|| anyhow::anyhow!("DHT not bootstrapped yet"))?;
+        let infohash = self
+            .dht_ids
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("no infohash"))?
+            .as_bytes();
+        publish_descriptor(&dht, &self.config.signing_key, infohash, descriptor).await?;
+        self.state.descriptor.set(Some(descriptor.clone())).ok();
+        Ok(())
+    }
+
+    /// Publish the members revoked from this topic next to its descriptor, replacing
+    /// the previous list. Only the owner can publish, members fetch the list every
+    /// announce interval and stop admitting them.
+    pub async fn publish_revocations(&self, revoked: &[EndpointId]) -> anyhow::Result<()> {
+        let dht = self
+            .dht
+            .get()
+            .ok_or_else(|| anyhow::anyhow!("DHT not bootstrapped yet"))?;
+        let infohash = self
+            .dht_ids
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("no infohash"))?
+            .as_bytes();
+        publish_revocations(&dht, &self.config.signing_key, infohash, revoked).await?;
+        if let Some(membership) = &self.config.membership {
+            membership.apply_revocations(revoked.iter().copied());
+        }
+        Ok(())
+    }
+
+    /// Estimated number of active members, from distinct fresh records across
+    /// lookups and sampled shards.
+    pub fn population(&self) -> PopulationEstimate {
+        self.state.population.get()
+    }
+
+    /// Watch the population estimate, updated after every lookup.
+    pub fn population_watcher(&self) -> n0_watcher::Direct<PopulationEstimate> {
+        self.state.population.watch()
+    }
+
+    /// Which source found each connected neighbor.
+    pub async fn peer_sources(&self) -> HashMap<EndpointId, SourceKind> {
+        let neighbors = self.added_neighbors().await;
+        let sources = self.state.peer_sources.lock().await;
+        neighbors
+            .into_iter()
+            .filter_map(|peer| Some((peer, sources.get(&peer)?.0.clone())))
+            .collect()
+    }
+
+    /// Announce, lookup and connector counters, see `DiscoveryMetrics::to_prometheus`.
+    pub fn metrics(&self) -> DiscoveryMetrics {
+        self.state.metrics.snapshot()
+    }
+}
+
+/// Subscription of a compat scheme's topic, its receiver held by a drain task.
+#[derive(Debug)]
+struct CompatTopic {
+    sender: GossipSender,
+    /// Makes the drain task hand back the receiver
+    stop: tokio::sync::oneshot::Sender<()>,
+    drain: tokio::task::JoinHandle<GossipReceiver>,
+}
+
+impl CompatTopic {
+    fn new(sender: GossipSender, mut receiver: GossipReceiver) -> Self {
+        let (stop, mut stop_rx) = tokio::sync::oneshot::channel();
+        let drain = tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    // also when the handle is dropped
+                    _ = &mut stop_rx => return receiver,
+                    event = receiver.next() => {
+                        if event.is_none() {
+                            let _ = stop_rx.await;
+                            return receiver;
+                        }
+                    }
+                }
+            }
+        });
+        Self {
+            sender,
+            stop,
+            drain,
+        }
+    }
+}
+
+/// Returned (inside `anyhow::Error`) when a joined subscription misses its deadline.
+///
+/// Use `err.downcast_ref::<JoinTimeoutError>()` to inspect how far the join got.
+#[derive(Debug, Clone)]
+pub struct JoinTimeoutError {
+    /// Whether the gossip receiver reported `joined()` before the deadline
+    pub gossip_joined: bool,
+    /// Neighbors connected via discovery before the deadline
+    pub connected: usize,
+    /// Neighbors that were required
+    pub required: usize,
+    /// Time spent waiting
+    pub elapsed: Duration,
 }
 
+impl std::fmt::Display for JoinTimeoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "join timed out after {:?}: {}/{} peers connected (gossip joined: {})",
+            self.elapsed, self.connected, self.required, self.gossip_joined
+        )
+    }
+}
+
+impl std::error::Error for JoinTimeoutError {}
+
 impl Drop for TopicDiscoveryHandle {
     fn drop(&mut self) {
         self.stop();
@@ -277,95 +1062,590 @@ pub trait TopicDiscoveryExt {
         config: TopicDiscoveryConfig,
     ) -> anyhow::Result<(GossipSender, GossipReceiver, TopicDiscoveryHandle)>;
 
+    /// Like `subscribe_with_discovery_joined`, with full addresses for the bootstrap nodes.
     #[allow(async_fn_in_trait)]
-    async fn subscribe_with_discovery(
+    async fn subscribe_with_discovery_joined_with_addrs(
         &self,
         topic_id: Vec<u8>,
-        bootstrap_nodes: Vec<EndpointId>,
+        bootstrap_nodes: Vec<EndpointAddr>,
         config: TopicDiscoveryConfig,
     ) -> anyhow::Result<(GossipSender, GossipReceiver, TopicDiscoveryHandle)>;
-}
 
-impl TopicDiscoveryExt for iroh_gossip::net::Gossip {
-    async fn subscribe_with_discovery_joined(
+    /// Like `subscribe_with_discovery_joined`, but waits for `min_peers` neighbors
+    /// and gives up after `timeout` with a `JoinTimeoutError`.
+    #[allow(async_fn_in_trait)]
+    async fn subscribe_with_discovery_joined_timeout(
         &self,
         topic_id: Vec<u8>,
         bootstrap_nodes: Vec<EndpointId>,
         config: TopicDiscoveryConfig,
-    ) -> anyhow::Result<(GossipSender, GossipReceiver, TopicDiscoveryHandle)> {
-        tracing::info!("subscribe_with_discovery_joined: starting subscription");
-        let (sender, mut receiver, handle) = self
-            .subscribe_with_discovery(topic_id, bootstrap_nodes, config)
-            .await?;
-        tracing::info!("subscribe_with_discovery_joined: waiting for receiver.joined()");
-        receiver.joined().await?;
+        min_peers: usize,
+        timeout: Duration,
+    ) -> anyhow::Result<(GossipSender, GossipReceiver, TopicDiscoveryHandle)>;
 
-        while handle.added_connection_count().await < 1 {
-            tokio::time::sleep(Duration::from_millis(50)).await;
-        }
-        tracing::info!("subscribe_with_discovery_joined: joined successfully");
-        Ok((sender, receiver, handle))
-    }
+    /// Like `subscribe_with_discovery_joined_timeout`, with full addresses for the bootstrap nodes.
+    #[allow(async_fn_in_trait)]
+    async fn subscribe_with_discovery_joined_timeout_with_addrs(
+        &self,
+        topic_id: Vec<u8>,
+        bootstrap_nodes: Vec<EndpointAddr>,
+        config: TopicDiscoveryConfig,
+        min_peers: usize,
+        timeout: Duration,
+    ) -> anyhow::Result<(GossipSender, GossipReceiver, TopicDiscoveryHandle)>;
 
+    #[allow(async_fn_in_trait)]
     async fn subscribe_with_discovery(
         &self,
         topic_id: Vec<u8>,
         bootstrap_nodes: Vec<EndpointId>,
         config: TopicDiscoveryConfig,
-    ) -> anyhow::Result<(GossipSender, GossipReceiver, TopicDiscoveryHandle)> {
-        tracing::info!("subscribe_with_discovery: computing topic hash");
-        let topic_bytes = topic_hash_32(&topic_id);
-        tracing::debug!(
-            "subscribe_with_discovery: topic_hash={}",
-            hex::encode(topic_bytes)
-        );
-
-        tracing::info!("subscribe_with_discovery: subscribing to gossip topic");
-        let (sender, receiver) = self
-            .subscribe(
-                iroh_gossip::proto::TopicId::from_bytes(topic_bytes),
-                bootstrap_nodes,
-            )
-            .await?
-            .split();
+    ) -> anyhow::Result<(GossipSender, GossipReceiver, TopicDiscoveryHandle)>;
 
-        tracing::info!(
-            "subscribe_with_discovery: subscribed, spawning announce and discovery tasks"
+    /// Like `subscribe_with_discovery`, with full addresses for the bootstrap nodes.
+    ///
+    /// Addresses in `bootstrap_nodes` are registered with the endpoint before gossip
+    /// dials them, so they don't need to be resolvable through iroh's address lookup.
+    #[allow(async_fn_in_trait)]
+    async fn subscribe_with_discovery_with_addrs(
+        &self,
+        topic_id: Vec<u8>,
+        bootstrap_nodes: Vec<EndpointAddr>,
+        config: TopicDiscoveryConfig,
+    ) -> anyhow::Result<(GossipSender, GossipReceiver, TopicDiscoveryHandle)>;
+
+    /// Subscribe to the topic of `ticket`, dialing its bootstrap peers first.
+    ///
+    /// The ticket's namespace and secret replace those of `config`'s topic scheme.
+    /// With bootstrap peers in the ticket the DHT starts in the background, so an
+    /// unreachable DHT doesn't delay the subscription.
+    #[allow(async_fn_in_trait)]
+    async fn subscribe_with_ticket(
+        &self,
+        ticket: &TopicTicket,
+        config: TopicDiscoveryConfig,
+    ) -> anyhow::Result<(GossipSender, GossipReceiver, TopicDiscoveryHandle)>;
+
+    /// Run announce and discovery for a gossip topic the caller already subscribed to.
+    ///
+    /// The DHT infohash is derived from the `TopicId` directly, so the topic scheme
+    /// and compat schemes in `config` are not used.
+    #[allow(async_fn_in_trait)]
+    async fn attach_discovery(
+        &self,
+        topic_id: TopicId,
+        sender: GossipSender,
+        config: TopicDiscoveryConfig,
+    ) -> anyhow::Result<TopicDiscoveryHandle>;
+}
+
+impl TopicDiscoveryExt for iroh_gossip::net::Gossip {
+    async fn subscribe_with_discovery_joined(
+        &self,
+        topic_id: Vec<u8>,
+        bootstrap_nodes: Vec<EndpointId>,
+        config: TopicDiscoveryConfig,
+    ) -> anyhow::Result<(GossipSender, GossipReceiver, TopicDiscoveryHandle)> {
+        self.subscribe_with_discovery_joined_with_addrs(
+            topic_id,
+            bare_addrs(bootstrap_nodes),
+            config,
+        )
+        .await
+    }
+
+    async fn subscribe_with_discovery_joined_with_addrs(
+        &self,
+        topic_id: Vec<u8>,
+        bootstrap_nodes: Vec<EndpointAddr>,
+        config: TopicDiscoveryConfig,
+    ) -> anyhow::Result<(GossipSender, GossipReceiver, TopicDiscoveryHandle)> {
+        tracing::info!("subscribe_with_discovery_joined: starting subscription");
+        let (sender, mut receiver, handle) = self
+            .subscribe_with_discovery_with_addrs(topic_id, bootstrap_nodes, config)
+            .await?;
+        tracing::info!("subscribe_with_discovery_joined: waiting for receiver.joined()");
+        receiver.joined().await?;
+
+        handle.wait_for_connections(1).await;
+        tracing::info!("subscribe_with_discovery_joined: joined successfully");
+        Ok((sender, receiver, handle))
+    }
+
+    async fn subscribe_with_discovery_joined_timeout(
+        &self,
+        topic_id: Vec<u8>,
+        bootstrap_nodes: Vec<EndpointId>,
+        config: TopicDiscoveryConfig,
+        min_peers: usize,
+        timeout: Duration,
+    ) -> anyhow::Result<(GossipSender, GossipReceiver, TopicDiscoveryHandle)> {
+        self.subscribe_with_discovery_joined_timeout_with_addrs(
+            topic_id,
+            bare_addrs(bootstrap_nodes),
+            config,
+            min_peers,
+            timeout,
+        )
+        .await
+    }
+
+    async fn subscribe_with_discovery_joined_timeout_with_addrs(
+        &self,
+        topic_id: Vec<u8>,
+        bootstrap_nodes: Vec<EndpointAddr>,
+        config: TopicDiscoveryConfig,
+        min_peers: usize,
+        timeout: Duration,
+    ) -> anyhow::Result<(GossipSender, GossipReceiver, TopicDiscoveryHandle)> {
+        let start = tokio::time::Instant::now();
+        let deadline = start + timeout;
+        let timed_out = |gossip_joined: bool, connected: usize| JoinTimeoutError {
+            gossip_joined,
+            connected,
+            required: min_peers,
+            elapsed: start.elapsed(),
+        };
+
+        tracing::info!("subscribe_with_discovery_joined_timeout: starting subscription");
+        let Ok(subscribed) = tokio::time::timeout_at(
+            deadline,
+            self.subscribe_with_discovery_with_addrs(topic_id, bootstrap_nodes, config),
+        )
+        .await
+        else {
+            return Err(timed_out(false, 0).into());
+        };
+        let (sender, mut receiver, handle) = subscribed?;
+
+        tracing::info!("subscribe_with_discovery_joined_timeout: waiting for receiver.joined()");
+        match tokio::time::timeout_at(deadline, receiver.joined()).await {
+            Ok(joined) => joined?,
+            Err(_) => {
+                let connected = handle.added_connection_count().await;
+                return Err(timed_out(false, connected).into());
+            }
+        }
+
+        if tokio::time::timeout_at(deadline, handle.wait_for_connections(min_peers))
+            .await
+            .is_err()
+        {
+            let connected = handle.added_connection_count().await;
+            return Err(timed_out(true, connected).into());
+        }
+        tracing::info!(
+            "subscribe_with_discovery_joined_timeout: joined with {min_peers} peers after {:?}",
+            start.elapsed()
+        );
+        Ok((sender, receiver, handle))
+    }
+
+    async fn subscribe_with_discovery(
+        &self,
+        topic_id: Vec<u8>,
+        bootstrap_nodes: Vec<EndpointId>,
+        config: TopicDiscoveryConfig,
+    ) -> anyhow::Result<(GossipSender, GossipReceiver, TopicDiscoveryHandle)> {
+        self.subscribe_with_discovery_with_addrs(topic_id, bare_addrs(bootstrap_nodes), config)
+            .await
+    }
+
+    async fn subscribe_with_discovery_with_addrs(
+        &self,
+        topic_id: Vec<u8>,
+        bootstrap_nodes: Vec<EndpointAddr>,
+        config: TopicDiscoveryConfig,
+    ) -> anyhow::Result<(GossipSender, GossipReceiver, TopicDiscoveryHandle)> {
+        tracing::info!("subscribe_with_discovery: computing topic hash");
+        let topic_hashes = config.topic_hashes(&topic_id);
+        let topic_bytes = topic_hashes[0];
+        let dht_ids = topic_hashes
+            .iter()
+            .map(topic_dht_id)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        tracing::debug!(
+            "subscribe_with_discovery: topic_hash={} dht_ids={}",
+            hex::encode(topic_bytes),
+            dht_ids.len()
+        );
+
+        // register known addresses before gossip dials the bootstrap nodes
+        let addr_book = AddrBook::for_endpoint(&config.endpoint)?;
+        let bootstrap_ids: Vec<EndpointId> = bootstrap_nodes.iter().map(|addr| addr.id).collect();
+        for addr in bootstrap_nodes {
+            addr_book.add(addr);
+        }
+
+        tracing::info!("subscribe_with_discovery: subscribing to gossip topic");
+        let (sender, receiver) = self
+            .subscribe(TopicId::from_bytes(topic_bytes), bootstrap_ids.clone())
+            .await?
+            .split();
+
+        // peers on an old scheme only joined the old topic, keep a subscription for them
+        let mut compat_topics = Vec::with_capacity(topic_hashes.len() - 1);
+        for hash in &topic_hashes[1..] {
+            tracing::info!(
+                "subscribe_with_discovery: subscribing to compat topic {}",
+                hex::encode(hash)
+            );
+            let (sender, receiver) = self
+                .subscribe(TopicId::from_bytes(*hash), Vec::new())
+                .await?
+                .split();
+            compat_topics.push(CompatTopic::new(sender, receiver));
+        }
+
+        tracing::info!(
+            "subscribe_with_discovery: subscribed, spawning announce and discovery tasks"
+        );
+        let handle = start_discovery(
+            sender.clone(),
+            compat_topics,
+            dht_ids,
+            config,
+            bootstrap_ids,
+            addr_book,
+        )
+        .await?;
+
+        Ok((sender, receiver, handle))
+    }
+
+    async fn subscribe_with_ticket(
+        &self,
+        ticket: &TopicTicket,
+        mut config: TopicDiscoveryConfig,
+    ) -> anyhow::Result<(GossipSender, GossipReceiver, TopicDiscoveryHandle)> {
+        tracing::info!(
+            "subscribe_with_ticket: ticket with {} bootstrap peers",
+            ticket.bootstrap().len()
+        );
+        config.topic_scheme = ticket.topic_scheme(&config.topic_scheme);
+        if !ticket.bootstrap().is_empty() {
+            // dialed by the dispatcher ahead of DHT candidates, the DHT keeps running as fallback
+            config.discovery_sources.push(Arc::new(StaticSource::new(
+                SourceKind::Bootstrap,
+                ticket.bootstrap().iter().cloned().map(DiscoveredPeer::from),
+            )));
+        }
+        self.subscribe_with_discovery(ticket.topic().to_vec(), vec![], config)
+            .await
+    }
+
+    async fn attach_discovery(
+        &self,
+        topic_id: TopicId,
+        sender: GossipSender,
+        config: TopicDiscoveryConfig,
+    ) -> anyhow::Result<TopicDiscoveryHandle> {
+        tracing::info!("attach_discovery: attaching to existing gossip topic");
+        let dht_ids = vec![topic_dht_id(topic_id.as_bytes())?];
+        tracing::debug!(
+            "attach_discovery: topic_hash={}",
+            hex::encode(topic_id.as_bytes())
         );
 
-        let state = DiscoveryState::new(config.retry_interval);
+        let addr_book = AddrBook::for_endpoint(&config.endpoint)?;
+        start_discovery(sender, Vec::new(), dht_ids, config, Vec::new(), addr_book).await
+    }
+}
 
-        tracing::info!("subscribe_with_discovery: initializing shared DHT");
-        let mut tries = 0;
-        let dht = loop {
-            if let Ok(dht) = init_dht().await {
-                break Arc::new(dht);
+/// Bootstrap nodes without known addresses, resolved through the endpoint's address lookup.
+fn bare_addrs(ids: Vec<EndpointId>) -> Vec<EndpointAddr> {
+    ids.into_iter().map(EndpointAddr::from).collect()
+}
+
+/// Initializes the DHT and spawns the announce and discovery tasks for `sender`'s topic.
+///
+/// `dht_ids` holds one infohash per topic, `sender`'s first, then one per `compat_topics`.
+async fn start_discovery(
+    sender: GossipSender,
+    compat_topics: Vec<CompatTopic>,
+    dht_ids: Vec<dht::Id>,
+    config: TopicDiscoveryConfig,
+    bootstrap_nodes: Vec<EndpointId>,
+    addr_book: AddrBook,
+) -> anyhow::Result<TopicDiscoveryHandle> {
+    if let Some(membership) = &config.membership {
+        anyhow::ensure!(
+            dht_ids
+                .first()
+                .is_some_and(|id| *id.as_bytes() == membership.infohash()),
+            "membership is for another topic"
+        );
+        membership.bind(&config.endpoint);
+    }
+    if let Some(healing) = &config.partition_healing {
+        anyhow::ensure!(
+            dht_ids
+                .first()
+                .is_some_and(|id| *id.as_bytes() == healing.infohash()),
+            "partition healing is for another topic"
+        );
+    }
+    let (state, candidates) = DiscoveryState::new(&config, bootstrap_nodes, addr_book);
+    let dht_cell = Arc::new(DhtCell::new(config.signing_key.clone()));
+    if let (Some(healing), Some(membership)) = (&config.partition_healing, &config.membership) {
+        // members of a gated topic are only listed to admitted peers
+        healing.restrict_to(membership.clone());
+    }
+    let topic_senders = std::iter::once(sender)
+        .chain(compat_topics.iter().map(|compat| compat.sender.clone()))
+        .collect();
+    let mut tasks = vec![spawn_dispatch_task(
+        state.clone(),
+        candidates,
+        topic_senders,
+        config.clone(),
+    )];
+    let handle_ids = dht_ids.clone();
+    let infohashes = dht_ids
+        .iter()
+        .map(|id| *id.as_bytes())
+        .collect::<Vec<[u8; 20]>>();
+    for source in &config.discovery_sources {
+        tasks.push(spawn_source_task(
+            state.clone(),
+            source.clone(),
+            infohashes.clone(),
+            config.timings.dispatch_idle_poll,
+        ));
+    }
+
+    if let Some(lan) = &config.lan_discovery {
+        match LanDiscovery::bind(lan.clone(), config.signing_key.clone()) {
+            Ok(lan) => tasks.push(spawn_lan_task(
+                state.clone(),
+                lan,
+                infohashes.clone(),
+                config.clone(),
+            )),
+            Err(e) => tracing::warn!("start_discovery: LAN discovery unavailable: {e}"),
+        }
+    }
+
+    // the DHT is a fallback when other sources can find peers, don't block them on
+    // a DHT we might never reach
+    if config.lan_discovery.is_some() || !config.discovery_sources.is_empty() {
+        let state = state.clone();
+        let dht_cell = dht_cell.clone();
+        let config = config.clone();
+        tasks.push(tokio::spawn(async move {
+            match init_dht_with_retries(&state, &config).await {
+                Ok(dht) => {
+                    dht_cell.set(dht.clone());
+                    start_dht_tasks(state, dht, dht_ids, config).await;
+                }
+                Err(e) => tracing::warn!("start_discovery: continuing without the DHT: {e}"),
             }
-            tracing::warn!("subscribe_with_discovery: DHT init failed, retrying in 2s");
-            tokio::time::sleep(Duration::from_secs(2)).await;
-            tries += 1;
-            if let Some(retries) = config.dht_retries()
-                && tries > retries
-            {
-                anyhow::bail!("DHT init failed after 5 attempts");
+        }));
+    } else {
+        let dht = init_dht_with_retries(&state, &config).await?;
+        dht_cell.set(dht.clone());
+        tasks.extend(start_dht_tasks(state.clone(), dht, dht_ids, config.clone()).await);
+    }
+
+    Ok(TopicDiscoveryHandle {
+        state,
+        dht: dht_cell,
+        dht_ids: handle_ids,
+        config,
+        compat_topics: Mutex::new(compat_topics),
+        _tasks: tasks,
+    })
+}
+
+/// Spawns the tasks using the DHT, once the owner's descriptor was looked up so
+/// the first discovery round already applies its flags.
+async fn start_dht_tasks(
+    state: Arc<DiscoveryState>,
+    dht: Arc<AsyncDht>,
+    dht_ids: Vec<dht::Id>,
+    config: TopicDiscoveryConfig,
+) -> Vec<tokio::task::JoinHandle<()>> {
+    let mut tasks = Vec::new();
+    if let Some(owner) = config.topic_owner
+        && let Some(id) = dht_ids.first()
+    {
+        refresh_descriptor(&state, &dht, id.as_bytes(), owner, &config).await;
+        tasks.push(spawn_descriptor_task(
+            state.clone(),
+            dht.clone(),
+            *id.as_bytes(),
+            owner,
+            config.clone(),
+        ));
+    }
+    if let Some(membership) = &config.membership
+        && let Some(owner) = membership.owner()
+        && let Some(id) = dht_ids.first()
+    {
+        tasks.push(spawn_revocation_task(
+            state.clone(),
+            dht.clone(),
+            *id.as_bytes(),
+            owner,
+            membership.clone(),
+            config.clone(),
+        ));
+    }
+    match config.announce_shard_ids(&dht_ids) {
+        Ok(announce_ids) => tasks.push(spawn_announce_task(
+            state.clone(),
+            dht.clone(),
+            announce_ids,
+            config.clone(),
+        )),
+        Err(e) => tracing::error!("start_dht_tasks: invalid shard infohash: {e}"),
+    }
+    tasks.push(spawn_discovery_task(state, dht, dht_ids, config));
+    tasks
+}
+
+/// Refreshes the owner's topic descriptor every announce interval.
+fn spawn_descriptor_task(
+    state: Arc<DiscoveryState>,
+    dht: Arc<AsyncDht>,
+    infohash: [u8; 20],
+    owner: EndpointId,
+    config: TopicDiscoveryConfig,
+) -> tokio::task::JoinHandle<()> {
+    tracing::info!("spawn_descriptor_task: starting descriptor task");
+    tokio::spawn(async move {
+        loop {
+            let interval = if state.descriptor.get().is_some() {
+                config.announce_interval
+            } else {
+                config.discovery_interval
+            };
+            tokio::time::sleep(config.timings.jittered(interval)).await;
+            if state.is_stopped() {
+                break;
             }
-        };
+            refresh_descriptor(&state, &dht, &infohash, owner, &config).await;
+        }
+        tracing::info!("descriptor_task: stopped");
+    })
+}
 
-        let tasks = vec![
-            spawn_announce_task(state.clone(), dht.clone(), topic_bytes, config.clone()),
-            spawn_discovery_task(state.clone(), dht, sender.clone(), topic_bytes, config),
-        ];
+/// Fetches the owner's topic descriptor and keeps it if it's newer than ours.
+async fn refresh_descriptor(
+    state: &DiscoveryState,
+    dht: &AsyncDht,
+    infohash: &[u8; 20],
+    owner: EndpointId,
+    config: &TopicDiscoveryConfig,
+) {
+    match time::timeout(
+        config.timings.lookup_timeout,
+        fetch_descriptor(dht, owner, infohash),
+    )
+    .await
+    {
+        Ok(Ok(Some(descriptor))) => {
+            // never go back to an older version returned by a lagging node
+            let newer = state
+                .descriptor
+                .get()
+                .is_none_or(|current| descriptor.version > current.version);
+            if newer {
+                tracing::info!(
+                    "descriptor_task: topic descriptor version {}",
+                    descriptor.version
+                );
+                if let Some(membership) = &config.membership {
+                    require_announced_pow(membership, &descriptor, config);
+                } else if descriptor.flags.invite_only {
+                    tracing::warn!(
+                        "descriptor_task: topic is invite-only, candidates are refused without a membership"
+                    );
+                }
+                state.descriptor.set(Some(descriptor)).ok();
+            }
+        }
+        Ok(Ok(None)) => tracing::debug!("descriptor_task: no descriptor published"),
+        Ok(Err(e)) => tracing::warn!("descriptor_task: invalid descriptor: {e}"),
+        Err(_) => tracing::debug!("descriptor_task: lookup timed out"),
+    }
+}
 
-        let handle = TopicDiscoveryHandle {
-            state,
-            _tasks: tasks,
-        };
+/// Applies the owner's revocation list to `membership`, refreshed every announce interval.
+fn spawn_revocation_task(
+    state: Arc<DiscoveryState>,
+    dht: Arc<AsyncDht>,
+    infohash: [u8; 20],
+    owner: EndpointId,
+    membership: Membership,
+    config: TopicDiscoveryConfig,
+) -> tokio::task::JoinHandle<()> {
+    tracing::info!("spawn_revocation_task: starting revocation task");
+    tokio::spawn(async move {
+        while !state.is_stopped() {
+            match time::timeout(
+                config.timings.lookup_timeout,
+                fetch_revocations(&dht, owner, &infohash),
+            )
+            .await
+            {
+                Ok(Ok(Some(revoked))) => {
+                    tracing::debug!("revocation_task: {} revoked members", revoked.len());
+                    membership.apply_revocations(revoked);
+                }
+                Ok(Ok(None)) => tracing::debug!("revocation_task: no revocations published"),
+                Ok(Err(e)) => tracing::warn!("revocation_task: invalid revocation list: {e}"),
+                Err(_) => tracing::debug!("revocation_task: lookup timed out"),
+            }
+            tokio::time::sleep(config.timings.jittered(config.announce_interval)).await;
+        }
+        tracing::info!("revocation_task: stopped");
+    })
+}
 
-        Ok((sender, receiver, handle))
+/// Enforces the descriptor's proof-of-work difficulty in the handshake, warning if
+/// our own proof falls short of it.
+fn require_announced_pow(
+    membership: &Membership,
+    descriptor: &TopicDescriptor,
+    config: &TopicDiscoveryConfig,
+) {
+    let difficulty = descriptor.flags.pow_difficulty;
+    membership.require_announced_pow(difficulty);
+    let own_bits = membership.proof_of_work().map_or(0, |proof| {
+        proof.difficulty(&membership.infohash(), &config.endpoint.id())
+    });
+    if own_bits < u32::from(difficulty) {
+        tracing::warn!(
+            "descriptor_task: topic requires {difficulty} bits of work, our proof has {own_bits}"
+        );
+    }
+}
+
+async fn init_dht_with_retries(
+    state: &DiscoveryState,
+    config: &TopicDiscoveryConfig,
+) -> anyhow::Result<Arc<AsyncDht>> {
+    tracing::info!("start_discovery: initializing shared DHT");
+    let mut tries = 0;
+    loop {
+        anyhow::ensure!(!state.is_stopped(), "discovery stopped");
+        if let Ok(dht) = init_dht(&config.timings).await {
+            return Ok(Arc::new(dht));
+        }
+        let retry = config.timings.jittered(config.timings.dht_init_retry);
+        tracing::warn!("start_discovery: DHT init failed, retrying in {retry:?}");
+        tokio::time::sleep(retry).await;
+        tries += 1;
+        if let Some(retries) = config.dht_retries()
+            && tries > retries
+        {
+            anyhow::bail!("DHT init failed after {tries} attempts");
+        }
     }
 }
 
-async fn init_dht() -> anyhow::Result<AsyncDht> {
+async fn init_dht(timings: &Timings) -> anyhow::Result<AsyncDht> {
     tracing::info!("init_dht: building DHT with bootstrap nodes");
     let dht = dht::Dht::builder()
         .no_bootstrap()
@@ -374,7 +1654,7 @@ async fn init_dht() -> anyhow::Result<AsyncDht> {
         .as_async();
 
     tracing::info!("init_dht: waiting for DHT bootstrap... ");
-    match tokio::time::timeout(Duration::from_secs(15), dht.bootstrapped()).await {
+    match tokio::time::timeout(timings.dht_bootstrap_timeout, dht.bootstrapped()).await {
         Ok(true) => {}
         Ok(false) => {
             tracing::error!("init_dht: DHT bootstrap failed");
@@ -393,14 +1673,42 @@ fn spawn_connector(
     state: Arc<DiscoveryState>,
     gossip_sender: GossipSender,
     peer: EndpointId,
-    timeout: Duration,
-    endpoint: Endpoint,
+    config: &TopicDiscoveryConfig,
 ) {
+    let timeout = config.connection_timeout;
+    let poll_interval = config.timings.connector_poll_interval;
+    let endpoint = config.endpoint.clone();
+    let selector = config.peer_selector.clone();
+    let sources = config.discovery_sources.clone();
+    let membership = config.membership.clone();
+    state.metrics.connector_started();
     tokio::spawn(async move {
+        state.record_peer_outcome(peer, PeerOutcome::Pending).await;
         if state.is_stopped() {
             return;
         }
 
+        if let Some(membership) = &membership {
+            let admitted = match time::timeout(timeout, membership.handshake(&endpoint, peer)).await
+            {
+                Ok(Ok(())) => true,
+                Ok(Err(e)) => {
+                    tracing::debug!(
+                        "connector: membership handshake with {} failed: {e}",
+                        peer.fmt_short()
+                    );
+                    false
+                }
+                Err(_) => false,
+            };
+            if !admitted {
+                // keep the attempt mark so we don't retry before `retry_interval`
+                state.record_peer_outcome(peer, PeerOutcome::Rejected).await;
+                selector.record_outcome(&peer, false);
+                return;
+            }
+        }
+
         tracing::debug!("connector: joining peer {} via gossip", peer.fmt_short());
 
         let _ = gossip_sender.join_peers(vec![peer]).await;
@@ -411,14 +1719,13 @@ fn spawn_connector(
 
         let wait_for_connection = async {
             loop {
-                if let Some(remote_info) = endpoint.remote_info(peer).await
-                    && remote_info.addrs().any(|addr| {
-                        matches!(addr.usage(), iroh::endpoint::TransportAddrUsage::Active)
-                    })
-                {
+                if has_active_path(&endpoint, peer).await {
                     return true;
                 }
-                tokio::time::sleep(Duration::from_millis(250)).await;
+                if state.is_stopped() {
+                    return false;
+                }
+                tokio::time::sleep(poll_interval).await;
             }
         };
 
@@ -428,8 +1735,19 @@ fn spawn_connector(
                     "connector: successfully connected to peer {}",
                     peer.fmt_short()
                 );
-                let mut guard = state.once_connected_neighbors.lock().await;
-                guard.insert(peer);
+                state.add_neighbor(peer).await;
+                state
+                    .metrics
+                    .connector_succeeded(state.started_at.elapsed());
+                state
+                    .record_peer_outcome(peer, PeerOutcome::Connected)
+                    .await;
+                selector.record_outcome(&peer, true);
+                if let Some((_, found)) = state.peer_sources.lock().await.get(&peer) {
+                    for source in &sources {
+                        source.peer_connected(found);
+                    }
+                }
                 return;
             }
             Ok(false) => {
@@ -437,6 +1755,7 @@ fn spawn_connector(
                     "connector: stopped while waiting for connection to {}",
                     peer.fmt_short()
                 );
+                state.record_peer_outcome(peer, PeerOutcome::Aborted).await;
             }
             Err(_) => {
                 tracing::warn!(
@@ -444,6 +1763,9 @@ fn spawn_connector(
                     peer.fmt_short(),
                     timeout
                 );
+                state.metrics.connector_timed_out();
+                state.record_peer_outcome(peer, PeerOutcome::TimedOut).await;
+                selector.record_outcome(&peer, false);
             }
         }
 
@@ -451,63 +1773,299 @@ fn spawn_connector(
     });
 }
 
+fn spawn_lan_task(
+    state: Arc<DiscoveryState>,
+    lan: LanDiscovery,
+    infohashes: Vec<[u8; 20]>,
+    config: TopicDiscoveryConfig,
+) -> tokio::task::JoinHandle<()> {
+    tracing::info!("spawn_lan_task: starting LAN discovery task");
+    tokio::spawn(async move {
+        let mut announce = tokio::time::interval(lan.config().announce_interval);
+        announce.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        while !state.is_stopped() {
+            tokio::select! {
+                _ = announce.tick() => {
+                    let direct_addrs = own_direct_addrs(&config.endpoint);
+                    for infohash in &infohashes {
+                        if let Err(e) = lan.announce(*infohash, direct_addrs.clone()).await {
+                            tracing::debug!("lan_task: announce failed: {e}");
+                        }
+                    }
+                }
+                announcement = lan.recv() => {
+                    let announcement = match announcement {
+                        Ok(announcement) => announcement,
+                        Err(e) => {
+                            tracing::warn!("lan_task: receive failed: {e}");
+                            tokio::time::sleep(lan.config().announce_interval).await;
+                            continue;
+                        }
+                    };
+                    let Some(topic) = infohashes
+                        .iter()
+                        .position(|infohash| *infohash == announcement.infohash)
+                    else {
+                        continue;
+                    };
+                    state.submit_to_topic(
+                        SourceKind::Lan,
+                        DiscoveredPeer::new(announcement.endpoint_id)
+                            .with_direct_addrs(announcement.direct_addrs),
+                        topic,
+                    );
+                }
+            }
+        }
+        tracing::info!("lan_task: stopped");
+    })
+}
+
+/// Forwards the candidates of an extra `DiscoverySource` to the dispatch task.
+///
+/// A finished source is polled again every fallback round while the DHT is degraded.
+fn spawn_source_task(
+    state: Arc<DiscoveryState>,
+    source: Arc<dyn DiscoverySource>,
+    infohashes: Vec<[u8; 20]>,
+    idle_poll: Duration,
+) -> tokio::task::JoinHandle<()> {
+    let kind = source.kind();
+    tracing::info!("spawn_source_task: starting {kind:?} source");
+    tokio::spawn(async move {
+        let mut fallback_rounds = state.fallback_rounds.watch();
+        let mut peers = Some(source.peers(&infohashes));
+        while !state.is_stopped() {
+            let Some(stream) = &mut peers else {
+                if let Ok(Ok(round)) = time::timeout(idle_poll, fallback_rounds.updated()).await {
+                    tracing::debug!(
+                        "source_task: polling {kind:?} source again (fallback round {round})"
+                    );
+                    peers = Some(source.peers(&infohashes));
+                }
+                continue;
+            };
+            match time::timeout(idle_poll, stream.next()).await {
+                Ok(Some(found)) => state.submit(kind.clone(), found),
+                Ok(None) => {
+                    tracing::debug!("source_task: {kind:?} source finished");
+                    peers = None;
+                }
+                Err(_) => {}
+            }
+        }
+        tracing::debug!("source_task: {kind:?} source stopped");
+    })
+}
+
+/// Rate limited candidates the dispatcher holds on to, further ones are dropped until
+/// their source reports them again.
+const MAX_DEFERRED_CANDIDATES: usize = 1024;
+
+/// Dials candidates of all sources, highest priority first and within each source's rate limit.
+///
+/// `topics` holds the sender of the current topic first, then those of the compat topics.
+fn spawn_dispatch_task(
+    state: Arc<DiscoveryState>,
+    mut candidates: mpsc::UnboundedReceiver<Candidate>,
+    topics: Vec<GossipSender>,
+    config: TopicDiscoveryConfig,
+) -> tokio::task::JoinHandle<()> {
+    let my_key = config.signing_key.verifying_key().to_bytes();
+    let collect_window = config.timings.dispatch_batch_window;
+
+    tracing::info!("spawn_dispatch_task: starting dispatch task");
+    tokio::spawn(async move {
+        let mut limiter = DialLimiter::default();
+        // rate limited candidates, keyed by when their source may dial again
+        let mut deferred: BTreeMap<tokio::time::Instant, Vec<Candidate>> = BTreeMap::new();
+        let mut deferred_peers: HashSet<(EndpointId, usize)> = HashSet::new();
+        // topics each dialed peer was joined into
+        let mut joined: HashSet<(EndpointId, usize)> = HashSet::new();
+
+        while !state.is_stopped() {
+            let idle_until = tokio::time::Instant::now() + config.timings.dispatch_idle_poll;
+            let wake = deferred
+                .first_key_value()
+                .map_or(idle_until, |(due, _)| (*due).min(idle_until));
+            let mut batch: Vec<Candidate> = Vec::new();
+            match tokio::time::timeout_at(wake, candidates.recv()).await {
+                Ok(Some(candidate)) => {
+                    batch.push(candidate);
+                    // let candidates arriving together compete on priority
+                    let window_end = tokio::time::Instant::now() + collect_window;
+                    while let Ok(Some(candidate)) =
+                        tokio::time::timeout_at(window_end, candidates.recv()).await
+                    {
+                        batch.push(candidate);
+                    }
+                }
+                Ok(None) => break,
+                Err(_) => {}
+            }
+            let now = tokio::time::Instant::now();
+            while let Some(entry) = deferred.first_entry() {
+                if *entry.key() > now {
+                    break;
+                }
+                for candidate in entry.remove() {
+                    deferred_peers.remove(&(candidate.1.peer, candidate.2));
+                    batch.push(candidate);
+                }
+            }
+
+            if state.refuses_candidates(&config) {
+                tracing::debug!(
+                    "dispatch_task: refusing {} candidates of an invite-only topic without a membership",
+                    batch.len()
+                );
+                continue;
+            }
+            batch
+                .sort_by_key(|(kind, _, _)| std::cmp::Reverse(config.source_policy(kind).priority));
+            for (kind, found, topic) in batch {
+                let peer = found.peer;
+                let Some(gossip_sender) = topics.get(topic) else {
+                    continue;
+                };
+                if *peer.as_bytes() == my_key || deferred_peers.contains(&(peer, topic)) {
+                    continue;
+                }
+                if !state.can_attempt(*peer.as_bytes()).await {
+                    // a neighbor found under another topic's infohash runs both schemes
+                    if state.is_neighbor(&peer).await && joined.insert((peer, topic)) {
+                        tracing::debug!(
+                            "dispatch_task: joining neighbor {} into topic {topic}",
+                            peer.fmt_short()
+                        );
+                        let _ = gossip_sender.join_peers(vec![peer]).await;
+                    }
+                    continue;
+                }
+                let policy = config.source_policy(&kind);
+                if !limiter.try_acquire(&kind, &policy) {
+                    if deferred_peers.len() < MAX_DEFERRED_CANDIDATES {
+                        deferred_peers.insert((peer, topic));
+                        deferred
+                            .entry(limiter.available_at(&kind).into())
+                            .or_default()
+                            .push((kind, found, topic));
+                    } else {
+                        tracing::debug!(
+                            "dispatch_task: dropping rate limited {} found via {kind:?}",
+                            peer.fmt_short()
+                        );
+                    }
+                    continue;
+                }
+                if !state.should_attempt(*peer.as_bytes()).await {
+                    continue;
+                }
+                tracing::debug!(
+                    "dispatch_task: dialing {} found via {kind:?}",
+                    peer.fmt_short()
+                );
+                state.addr_book.add(EndpointAddr::from(&found));
+                state.peer_sources.lock().await.insert(peer, (kind, found));
+                joined.insert((peer, topic));
+                spawn_connector(state.clone(), gossip_sender.clone(), peer, &config);
+            }
+        }
+        tracing::info!("dispatch_task: stopped");
+    })
+}
+
+/// True if iroh currently uses a path to `peer`.
+async fn has_active_path(endpoint: &Endpoint, peer: EndpointId) -> bool {
+    endpoint.remote_info(peer).await.is_some_and(|remote_info| {
+        remote_info
+            .addrs()
+            .any(|addr| matches!(addr.usage(), iroh::endpoint::TransportAddrUsage::Active))
+    })
+}
+
 fn spawn_announce_task(
     state: Arc<DiscoveryState>,
     dht: Arc<AsyncDht>,
-    topic_hash_32: [u8; 32],
+    ids: Vec<dht::Id>,
     config: TopicDiscoveryConfig,
 ) -> tokio::task::JoinHandle<()> {
     tracing::info!("spawn_announce_task: starting announce task");
     tokio::spawn(async move {
-        let mut backoff = Duration::from_secs(5);
+        let timings = config.timings.clone();
+        let mut backoff = timings.announce_backoff_min;
         let mut round = 0u64;
 
-        let id = match dht::Id::from_bytes(topic_hash_20(&topic_hash_32)) {
-            Ok(id) => id,
-            Err(e) => {
-                tracing::error!("announce_task: invalid topic hash: {e}");
-                return;
-            }
-        };
-
         while !state.is_stopped() {
             round += 1;
             tracing::debug!("announce_task: round {round} starting");
 
-            tracing::debug!("announce_task: announcing to DHT");
+            let visible = state.visible_records_per_shard(&config).await;
+            let probability = config.announce_mode.announce_probability(visible);
+            if probability < 1.0 && rand::random::<f64>() >= probability {
+                tracing::debug!(
+                    "announce_task: skipping announce ({visible:?} records visible, p={probability:.2})"
+                );
+                state.metrics.announce_skipped();
+                tokio::time::sleep(timings.jittered(config.announce_interval)).await;
+                continue;
+            }
+
+            tracing::debug!(
+                "announce_task: announcing to DHT under {} infohashes",
+                ids.len()
+            );
             match tokio::time::timeout(
-                Duration::from_secs(30),
-                dht.announce_signed_peer(id, &config.signing_key),
+                timings.announce_timeout,
+                announce_all(&dht, &ids, &config.signing_key),
             )
             .await
             {
                 Ok(Ok(_)) => {
                     tracing::info!("announce_task: DHT announce success");
-                    backoff = Duration::from_secs(5);
-                    tracing::debug!("announce_task: sleeping for {:?}", config.announce_interval);
-                    tokio::time::sleep(config.announce_interval).await;
+                    state.metrics.announce_ok();
+                    state.announce_finished(AnnounceResult::Ok, None).await;
+                    if config.dht_addressing {
+                        publish_own_addr(&dht, &config).await;
+                    }
+                    publish_own_proof(&dht, &config).await;
+                    backoff = timings.announce_backoff_min;
+                    let interval = timings.jittered(config.announce_interval);
+                    tracing::debug!("announce_task: sleeping for {interval:?}");
+                    sleep_with_self_checks(&state, &dht, &ids, &config, interval).await;
                 }
                 Ok(Err(e)) => {
                     tracing::warn!(
                         "announce_task: DHT announce failed: {e}, retrying in {backoff:?}"
                     );
+                    state.metrics.announce_failed();
+                    state
+                        .announce_finished(AnnounceResult::Failed, Some(e.to_string()))
+                        .await;
 
                     // Token staleness fix: Do a fresh GET to acquire new tokens before retry.
                     // The PUT fails with NoClosestNodes when tokens expire (5min rotation).
                     // get_signed_peers forces the DHT to issue fresh tokens for our IP I think?!
                     tracing::debug!("announce_task: refreshing tokens via get_signed_peers");
-                    let mut stream = dht.get_signed_peers(id).await;
-                    let _ = stream.next().await;
+                    for id in &ids {
+                        let mut stream = dht.get_signed_peers(*id).await;
+                        let _ = stream.next().await;
+                    }
 
-                    tokio::time::sleep(backoff).await;
-                    backoff = (backoff * 2).min(Duration::from_secs(60));
+                    tokio::time::sleep(timings.jittered(backoff)).await;
+                    backoff = timings.next_announce_backoff(backoff);
                 }
                 Err(_) => {
                     tracing::warn!(
                         "announce_task: DHT announce timed out, retrying in {backoff:?}"
                     );
-                    tokio::time::sleep(backoff).await;
-                    backoff = (backoff * 2).min(Duration::from_secs(60));
+                    state.metrics.announce_timed_out();
+                    state
+                        .announce_finished(AnnounceResult::TimedOut, None)
+                        .await;
+                    tokio::time::sleep(timings.jittered(backoff)).await;
+                    backoff = timings.next_announce_backoff(backoff);
                 }
             }
         }
@@ -515,11 +2073,120 @@ fn spawn_announce_task(
     })
 }
 
+async fn publish_own_addr(dht: &AsyncDht, config: &TopicDiscoveryConfig) {
+    let addr = config.endpoint.addr();
+    match tokio::time::timeout(
+        config.timings.announce_timeout,
+        publish_addr(dht, &config.signing_key, &addr),
+    )
+    .await
+    {
+        Ok(Ok(())) => tracing::debug!("announce_task: published addressing record"),
+        Ok(Err(e)) => tracing::warn!("announce_task: publishing addressing record failed: {e}"),
+        Err(_) => tracing::warn!("announce_task: publishing addressing record timed out"),
+    }
+}
+
+/// Publishes the proof of our membership next to our announcement, if we have one.
+async fn publish_own_proof(dht: &AsyncDht, config: &TopicDiscoveryConfig) {
+    let Some(membership) = &config.membership else {
+        return;
+    };
+    let Some(proof) = membership.proof_of_work() else {
+        return;
+    };
+    match tokio::time::timeout(
+        config.timings.announce_timeout,
+        publish_proof(dht, &config.signing_key, &membership.infohash(), &proof),
+    )
+    .await
+    {
+        Ok(Ok(())) => tracing::debug!("announce_task: published proof of work"),
+        Ok(Err(e)) => tracing::warn!("announce_task: publishing proof of work failed: {e}"),
+        Err(_) => tracing::warn!("announce_task: publishing proof of work timed out"),
+    }
+}
+
+/// Sleeps until the next announce is due, verifying every `self_check_interval`
+/// that our record is retrievable. Returns early if it is not, so we re-announce.
+async fn sleep_with_self_checks(
+    state: &DiscoveryState,
+    dht: &AsyncDht,
+    ids: &[dht::Id],
+    config: &TopicDiscoveryConfig,
+    interval: Duration,
+) {
+    let next_announce = Instant::now() + interval;
+    loop {
+        let remaining = next_announce.saturating_duration_since(Instant::now());
+        let Some(check_interval) = config.self_check_interval else {
+            tokio::time::sleep(remaining).await;
+            return;
+        };
+        if remaining <= check_interval {
+            tokio::time::sleep(remaining).await;
+            return;
+        }
+        tokio::time::sleep(config.timings.jittered(check_interval)).await;
+        if state.is_stopped() {
+            return;
+        }
+
+        let visible = verify_own_announcement(dht, ids, config).await;
+        state.set_announcement_visible(visible);
+        if !visible {
+            tracing::warn!("announce_task: own announcement not visible, re-announcing");
+            return;
+        }
+        tracing::debug!("announce_task: own announcement verified");
+    }
+}
+
+/// Looks up every infohash and checks our key comes back with a fresh timestamp.
+async fn verify_own_announcement(
+    dht: &AsyncDht,
+    ids: &[dht::Id],
+    config: &TopicDiscoveryConfig,
+) -> bool {
+    let my_key = config.signing_key.verifying_key().to_bytes();
+    let max_age = config.max_record_age();
+    for id in ids {
+        let mut stream = dht.get_signed_peers(*id).await;
+        let deadline = tokio::time::Instant::now() + config.timings.lookup_timeout;
+        let mut found = false;
+        while let Ok(Some(items)) = tokio::time::timeout_at(deadline, stream.next()).await {
+            found = items.iter().any(|item| {
+                *item.key() == my_key
+                    && config.staleness_policy.classify(item.timestamp(), max_age)
+                        == RecordFreshness::Fresh
+            });
+            if found {
+                break;
+            }
+        }
+        if !found {
+            return false;
+        }
+    }
+    true
+}
+
+/// Announce under every infohash, fails if any single announce fails.
+async fn announce_all(
+    dht: &AsyncDht,
+    ids: &[dht::Id],
+    signing_key: &SigningKey,
+) -> anyhow::Result<()> {
+    for id in ids {
+        dht.announce_signed_peer(*id, signing_key).await?;
+    }
+    Ok(())
+}
+
 fn spawn_discovery_task(
     state: Arc<DiscoveryState>,
     dht: Arc<AsyncDht>,
-    gossip_sender: GossipSender,
-    topic_hash_32: [u8; 32],
+    ids: Vec<dht::Id>,
     config: TopicDiscoveryConfig,
 ) -> tokio::task::JoinHandle<()> {
     let my_key = config.signing_key.verifying_key().to_bytes();
@@ -530,14 +2197,12 @@ fn spawn_discovery_task(
 
         let mut no_peer_backoff = config.discovery_interval_no_peers;
         let backoff_increment = config.discovery_interval_no_peers;
-
-        let id = match dht::Id::from_bytes(topic_hash_20(&topic_hash_32)) {
-            Ok(id) => id,
-            Err(e) => {
-                tracing::error!("discovery_task: invalid topic hash: {e}");
-                return;
-            }
-        };
+        let mut degraded_lookups = 0usize;
+        let mut estimator = PopulationEstimator::new(config.shards, config.max_record_age());
+        let mut scheduler = AdaptiveScheduler::new(config.discovery_interval_first_connected);
+        // proofs are bound to the unsharded topic infohash
+        let pow_infohash = ids.first().map(|id| *id.as_bytes()).unwrap_or_default();
+        let mut proofs = ProofChecks::new(config.retry_interval);
 
         while !state.is_stopped() {
             round = round.saturating_add(1);
@@ -548,53 +2213,181 @@ fn spawn_discovery_task(
             );
 
             tracing::debug!("discovery_task: querying DHT for peers");
-            let peers = collect_peers_with_timeout(
-                &dht,
-                id,
-                Duration::from_secs(30),
-                config.announce_interval,
-            )
-            .await;
-            tracing::debug!("discovery_task: found {} peers from DHT", peers.len());
+            let max_peers = config.max_peers_per_round.unwrap_or(usize::MAX);
+            let mut filter = LookupFilter::new(
+                my_key,
+                config.staleness_policy.clone(),
+                config.max_record_age(),
+            );
+            let (lookup_ids, lookup_shards) = match config.lookup_shard_ids(&ids) {
+                Ok(lookup) => lookup,
+                Err(e) => {
+                    tracing::error!("discovery_task: invalid shard infohash: {e}");
+                    break;
+                }
+            };
+            let lookup_started = Instant::now();
+            // lookup ids are grouped by topic, `lookup_shards.len()` per topic
+            let mut streams = Vec::with_capacity(lookup_ids.len());
+            for (index, id) in lookup_ids.iter().enumerate() {
+                let topic = index / lookup_shards.len().max(1);
+                streams.push(
+                    dht.get_signed_peers(*id)
+                        .await
+                        .map(move |items| (topic, items)),
+                );
+            }
+            let mut stream = race_all(streams);
+            let deadline = tokio::time::Instant::now() + config.timings.lookup_timeout;
 
+            // dial fresh records as soon as their batch arrives instead of waiting for the lookup to finish
             let mut spawned: usize = 0;
-            for key_bytes in peers
-                .iter()
-                .take(config.max_peers_per_round.unwrap_or(usize::MAX))
+            while let Ok(Some((topic, items))) =
+                tokio::time::timeout_at(deadline, stream.next()).await
             {
-                if *key_bytes == my_key {
-                    continue;
-                }
+                tracing::debug!(
+                    "discovery_task: received batch of {} signed peers from DHT",
+                    items.len()
+                );
 
-                if !state.should_attempt(*key_bytes).await {
-                    continue;
+                let mut candidates = Vec::with_capacity(items.len());
+                for item in items {
+                    if !filter.accept(item.key(), item.timestamp(), topic)
+                        || !state.can_attempt(*item.key()).await
+                    {
+                        continue;
+                    }
+
+                    let Some(peer) = ed25519_dalek::VerifyingKey::from_bytes(item.key())
+                        .ok()
+                        .map(iroh::PublicKey::from_verifying_key)
+                    else {
+                        continue;
+                    };
+                    candidates.push(PeerCandidate {
+                        peer,
+                        timestamp: item.timestamp(),
+                    });
                 }
 
-                let Some(peer) = ed25519_dalek::VerifyingKey::from_bytes(key_bytes)
-                    .ok()
-                    .map(iroh::PublicKey::from_verifying_key)
-                else {
+                let budget = max_peers.saturating_sub(spawned);
+                let pow_difficulty = required_pow(&state, &config);
+                if pow_difficulty > 0 {
+                    candidates
+                        .retain(|candidate| proofs.may_check(&candidate.peer, pow_difficulty));
+                }
+                if candidates.is_empty() || budget == 0 {
                     continue;
-                };
+                }
+                // select before fetching proofs, a selected peer without one is skipped
+                let mut selected = config.peer_selector.select(candidates, budget);
+                if pow_difficulty > 0 {
+                    let unproven = selected.len();
+                    selected = proven_candidates(
+                        &dht,
+                        selected,
+                        pow_infohash,
+                        pow_difficulty,
+                        &mut proofs,
+                        deadline,
+                    )
+                    .await;
+                    tracing::debug!(
+                        "discovery_task: {} of {unproven} candidates proved {pow_difficulty} bits of work",
+                        selected.len()
+                    );
+                }
+                for candidate in selected {
+                    submit_dht_candidate(&state, &dht, candidate.peer, topic, &config);
+                    spawned = spawned.saturating_add(1);
+                }
+            }
+            let counts = filter.counts();
+            let population = estimator.lookup_finished(&lookup_shards, filter.fresh_keys());
+            tracing::debug!(
+                "discovery_task: population estimate {} ({} observed, {:?})",
+                population.estimate,
+                population.observed,
+                population.confidence
+            );
+            state.population.set(population).ok();
+            tracing::debug!(
+                "discovery_task: found {} fresh peers from DHT ({} records, {} stale)",
+                counts.fresh,
+                counts.seen,
+                counts.stale
+            );
+            state
+                .metrics
+                .lookup_finished(lookup_started.elapsed(), counts);
+
+            let info = dht.info().await;
+            let health = state
+                .lookup_finished(LookupDiagnostics {
+                    responding_nodes: info.routing_table_size(),
+                    supporting_nodes: info.singing_peers_routing_table_size(),
+                    records: counts.seen as usize,
+                    fresh_records: counts.fresh as usize,
+                    stale_records: counts.stale as usize,
+                    future_records: counts.from_future as usize,
+                })
+                .await;
+            tracing::debug!(
+                "discovery_task: dht health {health:?} (routing table: {}, signed peers routing table: {})",
+                info.routing_table_size(),
+                info.singing_peers_routing_table_size()
+            );
 
-                spawn_connector(
-                    state.clone(),
-                    gossip_sender.clone(),
-                    peer,
-                    config.connection_timeout,
-                    config.endpoint.clone(),
+            if health.is_degraded() {
+                degraded_lookups = degraded_lookups.saturating_add(1);
+            } else {
+                degraded_lookups = 0;
+            }
+            if degraded_lookups >= config.fallback_after_lookups.max(1) {
+                tracing::info!(
+                    "discovery_task: dht {health:?} for {degraded_lookups} lookups, falling back to the other sources"
                 );
-                spawned = spawned.saturating_add(1);
+                degraded_lookups = 0;
+                for peer in &state.bootstrap_nodes {
+                    if state.can_attempt(*peer.as_bytes()).await {
+                        state.submit(SourceKind::Bootstrap, DiscoveredPeer::new(*peer));
+                        spawned = spawned.saturating_add(1);
+                    }
+                }
+                state
+                    .fallback_rounds
+                    .set(state.fallback_rounds.get().saturating_add(1))
+                    .ok();
+            }
+
+            if let Some(healing) = &config.partition_healing {
+                let healed = heal_partitions(
+                    &state,
+                    &dht,
+                    healing,
+                    &filter.topic_keys(0),
+                    &mut proofs,
+                    &config,
+                )
+                .await;
+                spawned = spawned.saturating_add(healed);
             }
 
             if spawned > 0 {
-                tracing::info!("discovery_task: spawned {spawned} connector tasks");
+                tracing::info!("discovery_task: submitted {spawned} candidates");
             }
 
             let has_connection = state.has_connections().await;
             let interval = if has_connection {
                 no_peer_backoff = config.discovery_interval_no_peers;
-                if state.first_connected_phase(&config) {
+                if config.discovery_schedule == DiscoverySchedule::Adaptive {
+                    scheduler.next_interval(
+                        estimator.new_peers(),
+                        state.live_neighbors(&config.endpoint).await,
+                        config.discovery_interval_first_connected,
+                        config.discovery_interval,
+                    )
+                } else if state.first_connected_phase(&config) {
                     config.discovery_interval_first_connected
                 } else {
                     config.discovery_interval
@@ -612,7 +2405,7 @@ fn spawn_discovery_task(
                 has_connection,
                 no_peer_backoff
             );
-            tokio::time::sleep(interval).await;
+            tokio::time::sleep(config.timings.jittered(interval)).await;
             if !has_connection && state.has_connections().await {
                 let additional_interval = if state.first_connected_phase(&config) {
                     config.discovery_interval_first_connected
@@ -627,79 +2420,500 @@ fn spawn_discovery_task(
                     state.has_connections().await,
                     no_peer_backoff
                 );
-                tokio::time::sleep(additional_interval).await;
+                tokio::time::sleep(config.timings.jittered(additional_interval)).await;
             }
         }
         tracing::info!("discovery_task: stopped");
     })
 }
 
-async fn collect_peers_with_timeout(
-    dht: &AsyncDht,
-    id: dht::Id,
-    timeout: Duration,
-    announce_interval: Duration,
-) -> Vec<[u8; 32]> {
-    use futures_lite::StreamExt;
-
-    tracing::debug!("collect_peers_with_timeout: starting peer collection");
-    let mut stream = dht.get_signed_peers(id).await;
-    let deadline = tokio::time::Instant::now() + timeout;
-    let mut valid_items = Vec::new();
-    while let Ok(Some(items)) = tokio::time::timeout_at(deadline, stream.next()).await {
-        tracing::debug!(
-            "collect_peers_with_timeout: received batch of {} signed peers from DHT",
-            items.len()
-        );
-        for item in items {
-            let key_hex = hex::encode(&item.key()[..8]); // First 8 bytes for brevity
+/// Dial random fresh records that aren't neighbors and compare member digests
+/// with a random neighbor. Returns the number of submitted candidates.
+async fn heal_partitions(
+    state: &Arc<DiscoveryState>,
+    dht: &Arc<AsyncDht>,
+    healing: &PartitionHealing,
+    fresh_keys: &HashSet<[u8; 32]>,
+    proofs: &mut ProofChecks,
+    config: &TopicDiscoveryConfig,
+) -> usize {
+    let live = state.live_neighbors(&config.endpoint).await;
+    let fresh: Vec<EndpointId> = fresh_keys
+        .iter()
+        .filter_map(|key| EndpointId::from_bytes(key).ok())
+        .collect();
+    healing.observe(
+        config.endpoint.id(),
+        fresh.iter().chain(live.iter()).copied(),
+        config.max_record_age(),
+    );
+
+    // the selector prefers the newest announcers, sample uniformly to reach other clusters
+    let mut samples = Vec::new();
+    for peer in fresh {
+        if !live.contains(&peer) && state.can_attempt(*peer.as_bytes()).await {
+            samples.push(peer);
+        }
+    }
+    samples.sort_by_cached_key(|_| rand::random::<u64>());
+    samples.truncate(healing.samples());
+    let samples = with_required_proofs(state, dht, healing, samples, proofs, config).await;
+    let mut submitted = samples.len();
+    for peer in samples {
+        submit_dht_candidate(state, dht, peer, 0, config);
+    }
+
+    let Some(neighbor) = live.into_iter().min_by_key(|_| rand::random::<u64>()) else {
+        return submitted;
+    };
+    match time::timeout(
+        config.connection_timeout,
+        healing.exchange(&config.endpoint, neighbor),
+    )
+    .await
+    {
+        Ok(Ok(None)) => {
             tracing::debug!(
-                "collect_peers_with_timeout: peer key={key_hex}... timestamp={}",
-                item.timestamp()
+                "partition_healing: member digest matches {}",
+                neighbor.fmt_short()
             );
+        }
+        Ok(Ok(Some(comparison))) if comparison.unknown.is_empty() => {
+            tracing::debug!(
+                "partition_healing: member digest differs from {}, but it knows no members we don't",
+                neighbor.fmt_short()
+            );
+        }
+        Ok(Ok(Some(comparison))) => {
+            if comparison.is_partition() {
+                tracing::info!(
+                    "partition_healing: we know {:.0}% of the members {} sampled, possible partition",
+                    comparison.overlap * 100.0,
+                    neighbor.fmt_short()
+                );
+                state.metrics.partition_suspected();
+            } else {
+                tracing::debug!(
+                    "partition_healing: {} knows {} members we don't",
+                    neighbor.fmt_short(),
+                    comparison.unknown.len()
+                );
+            }
+            let unknown =
+                with_required_proofs(state, dht, healing, comparison.unknown, proofs, config).await;
+            for peer in unknown {
+                if state.can_attempt(*peer.as_bytes()).await {
+                    state.submit(SourceKind::PeerExchange, DiscoveredPeer::new(peer));
+                    submitted = submitted.saturating_add(1);
+                }
+            }
+        }
+        Ok(Err(e)) => tracing::debug!(
+            "partition_healing: digest exchange with {} failed: {e}",
+            neighbor.fmt_short()
+        ),
+        Err(_) => tracing::debug!(
+            "partition_healing: digest exchange with {} timed out",
+            neighbor.fmt_short()
+        ),
+    }
+    submitted
+}
 
-            let now = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap_or_default()
-                .as_micros();
+/// Drops `peers` without a sufficient proof of work if the topic requires one.
+async fn with_required_proofs(
+    state: &DiscoveryState,
+    dht: &Arc<AsyncDht>,
+    healing: &PartitionHealing,
+    peers: Vec<EndpointId>,
+    proofs: &mut ProofChecks,
+    config: &TopicDiscoveryConfig,
+) -> Vec<EndpointId> {
+    let pow_difficulty = required_pow(state, config);
+    if pow_difficulty == 0 {
+        return peers;
+    }
+    let candidates = peers
+        .into_iter()
+        .filter(|peer| proofs.may_check(peer, pow_difficulty))
+        .map(|peer| PeerCandidate { peer, timestamp: 0 })
+        .collect();
+    // the healing infohash is the topic's, checked when discovery starts
+    let deadline = tokio::time::Instant::now() + config.connection_timeout;
+    proven_candidates(
+        dht,
+        candidates,
+        healing.infohash(),
+        pow_difficulty,
+        proofs,
+        deadline,
+    )
+    .await
+    .into_iter()
+    .map(|candidate| candidate.peer)
+    .collect()
+}
 
-            let max_age = announce_interval.as_micros() + 10_000_000; // announce + 10s buffer
-            let age = now.saturating_sub(item.timestamp() as u128);
-            if age > max_age {
+/// Hands a DHT candidate to the dispatcher, with its addressing record if enabled.
+fn submit_dht_candidate(
+    state: &Arc<DiscoveryState>,
+    dht: &Arc<AsyncDht>,
+    peer: EndpointId,
+    topic: usize,
+    config: &TopicDiscoveryConfig,
+) {
+    if !config.dht_addressing {
+        state.submit_to_topic(SourceKind::Dht, DiscoveredPeer::new(peer), topic);
+        return;
+    }
+    let state = state.clone();
+    let dht = dht.clone();
+    let timeout = config.timings.addr_resolve_timeout;
+    tokio::spawn(async move {
+        let found = match time::timeout(timeout, resolve_addr(&dht, peer)).await {
+            Ok(Some(addr)) => DiscoveredPeer::from(addr),
+            _ => {
                 tracing::debug!(
-                    "collect_peers_with_timeout: skipping stale peer {key_hex}... (age: {}ms, max: {}ms)",
-                    age / 1000,
-                    max_age / 1000
+                    "discovery_task: no addressing record for {}",
+                    peer.fmt_short()
                 );
-                continue;
+                DiscoveredPeer::new(peer)
             }
+        };
+        state.submit_to_topic(SourceKind::Dht, found, topic);
+    });
+}
 
-            if !valid_items.contains(&item) {
-                valid_items.push(item);
-            }
+/// Leading zero bits candidates must prove before we pick them, from our membership
+/// and the owner's descriptor, 0 if none.
+fn required_pow(state: &DiscoveryState, config: &TopicDiscoveryConfig) -> u8 {
+    let announced = state
+        .descriptor
+        .get()
+        .map_or(0, |descriptor| descriptor.flags.pow_difficulty);
+    config
+        .membership
+        .as_ref()
+        .map_or(0, Membership::pow_difficulty)
+        .max(announced)
+}
+
+/// Proof lookups in flight at a time.
+const MAX_PROOF_FETCHES: usize = 16;
+
+/// Proofs of work we fetched, by peer.
+#[derive(Debug)]
+struct ProofChecks {
+    /// Leading zero bits of the peer's published proof, 0 if it had none, and when we
+    /// fetched it. Proofs stay valid for the endpoint and topic.
+    checked: HashMap<EndpointId, (u32, Instant)>,
+    /// How long missing and too weak proofs aren't fetched again
+    retry_interval: Duration,
+}
+
+impl ProofChecks {
+    fn new(retry_interval: Duration) -> Self {
+        Self {
+            checked: HashMap::new(),
+            retry_interval,
         }
     }
 
-    valid_items.sort_by_key(|item| item.timestamp());
-    valid_items.reverse();
-    valid_items.dedup_by_key(|item| item.key().to_vec());
+    fn is_proven(&self, peer: &EndpointId, difficulty: u8) -> bool {
+        self.checked
+            .get(peer)
+            .is_some_and(|(bits, _)| *bits >= u32::from(difficulty))
+    }
 
-    tracing::debug!(
-        "collect_peers_with_timeout: finished with {} peers",
-        valid_items.len()
-    );
-    valid_items.iter().map(|item| *item.key()).collect()
+    /// False while a missing or too weak proof of `peer` waits for `retry_interval`.
+    fn may_check(&self, peer: &EndpointId, difficulty: u8) -> bool {
+        self.is_proven(peer, difficulty)
+            || self
+                .checked
+                .get(peer)
+                .is_none_or(|(_, checked_at)| checked_at.elapsed() > self.retry_interval)
+    }
+
+    fn record(&mut self, peer: EndpointId, bits: u32) {
+        let retry_interval = self.retry_interval;
+        self.checked
+            .retain(|_, (bits, checked_at)| *bits > 0 || checked_at.elapsed() <= retry_interval);
+        self.checked.insert(peer, (bits, Instant::now()));
+    }
+}
+
+/// Keeps the candidates whose published proof for `infohash` has `difficulty` bits,
+/// fetching proofs we haven't checked recently, `MAX_PROOF_FETCHES` at a time until
+/// `deadline`.
+async fn proven_candidates(
+    dht: &Arc<AsyncDht>,
+    candidates: Vec<PeerCandidate>,
+    infohash: [u8; 20],
+    difficulty: u8,
+    proofs: &mut ProofChecks,
+    deadline: tokio::time::Instant,
+) -> Vec<PeerCandidate> {
+    let permits = Arc::new(tokio::sync::Semaphore::new(MAX_PROOF_FETCHES));
+    let mut fetches = tokio::task::JoinSet::new();
+    for candidate in &candidates {
+        if proofs.is_proven(&candidate.peer, difficulty)
+            || !proofs.may_check(&candidate.peer, difficulty)
+        {
+            continue;
+        }
+        let dht = dht.clone();
+        let peer = candidate.peer;
+        let permits = permits.clone();
+        fetches.spawn(async move {
+            let _permit = permits.acquire_owned().await.ok()?;
+            Some((peer, fetch_proof(&dht, peer, &infohash).await))
+        });
+    }
+    while let Ok(Some(fetch)) = tokio::time::timeout_at(deadline, fetches.join_next()).await {
+        if let Ok(Some((peer, proof))) = fetch {
+            let bits = proof.map_or(0, |proof| proof.difficulty(&infohash, &peer));
+            proofs.record(peer, bits);
+        }
+    }
+    candidates
+        .into_iter()
+        .filter(|candidate| proofs.is_proven(&candidate.peer, difficulty))
+        .collect()
+}
+
+/// Merges streams, e.g. the `get_signed_peers` streams of all infohashes, yielding
+/// items as soon as any stream has one.
+fn race_all<S>(streams: Vec<S>) -> futures_lite::stream::Boxed<S::Item>
+where
+    S: futures_lite::Stream + Send + 'static,
+    S::Item: Send + 'static,
+{
+    let mut merged = futures_lite::stream::empty().boxed();
+    for stream in streams {
+        merged = merged.race(stream).boxed();
+    }
+    merged
+}
+
+/// Drops our own, stale and already seen records within one lookup.
+struct LookupFilter {
+    my_key: [u8; 32],
+    policy: StalenessPolicy,
+    max_age: Duration,
+    seen: HashSet<[u8; 32]>,
+    /// Fresh keys per topic index, a peer may announce under several schemes
+    seen_in_topic: HashSet<([u8; 32], usize)>,
+    counts: LookupCounts,
 }
 
-fn topic_hash_32(topic_bytes: &Vec<u8>) -> [u8; 32] {
-    let mut hasher = sha2::Sha512::new();
-    hasher.update("/iroh/topic-discovery/v2");
-    hasher.update(topic_bytes);
-    hasher.finalize()[..32].try_into().expect("hashing failed")
+impl LookupFilter {
+    fn new(my_key: [u8; 32], policy: StalenessPolicy, max_age: Duration) -> Self {
+        Self {
+            my_key,
+            policy,
+            max_age,
+            seen: HashSet::new(),
+            seen_in_topic: HashSet::new(),
+            counts: LookupCounts::default(),
+        }
+    }
+
+    /// Returns true the first time a fresh record of another peer is seen in `topic`.
+    fn accept(&mut self, key: &[u8; 32], timestamp: u64, topic: usize) -> bool {
+        let key_hex = hex::encode(&key[..8]); // First 8 bytes for brevity
+        tracing::debug!("lookup_filter: peer key={key_hex}... timestamp={timestamp}");
+        self.counts.seen += 1;
+
+        let freshness = self.policy.classify(timestamp, self.max_age);
+        if !self.policy.accepts(freshness) {
+            match freshness {
+                RecordFreshness::Stale => {
+                    self.counts.stale += 1;
+                    tracing::debug!(
+                        "lookup_filter: skipping stale peer {key_hex}... (max age: {:?})",
+                        self.max_age
+                    )
+                }
+                RecordFreshness::FromFuture => {
+                    self.counts.from_future += 1;
+                    tracing::debug!(
+                        "lookup_filter: skipping peer {key_hex}... from the future (max skew: {:?})",
+                        self.policy.max_future_skew
+                    )
+                }
+                _ => tracing::warn!(
+                    "lookup_filter: skipping peer {key_hex}..., local clock is before unix epoch"
+                ),
+            }
+            return false;
+        }
+
+        if *key == self.my_key || !self.seen_in_topic.insert((*key, topic)) {
+            return false;
+        }
+        if self.seen.insert(*key) {
+            self.counts.fresh += 1;
+        }
+        true
+    }
+
+    fn counts(&self) -> LookupCounts {
+        self.counts
+    }
+
+    fn fresh_keys(&self) -> &HashSet<[u8; 32]> {
+        &self.seen
+    }
+
+    /// Fresh keys found under the infohashes of `topic`.
+    fn topic_keys(&self, topic: usize) -> HashSet<[u8; 32]> {
+        self.seen_in_topic
+            .iter()
+            .filter(|(_, seen_topic)| *seen_topic == topic)
+            .map(|(key, _)| *key)
+            .collect()
+    }
 }
 
-fn topic_hash_20(topic_hash_32: &[u8; 32]) -> [u8; 20] {
-    let mut hasher = sha2::Sha512::new();
-    hasher.update(topic_hash_32);
-    hasher.finalize()[..20].try_into().expect("hashing failed")
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        descriptor::TopicFlags,
+        pow::ProofOfWork,
+        test_utils::{endpoint, testnet_dht},
+    };
+
+    async fn test_config(timings: Timings) -> anyhow::Result<TopicDiscoveryConfig> {
+        Ok(TopicDiscoveryConfig::builder(endpoint().await?)
+            .timings(timings)
+            .build())
+    }
+
+    #[tokio::test]
+    async fn self_check_finds_only_announced_records() -> anyhow::Result<()> {
+        let (_testnet, dht) = testnet_dht().await?;
+        let config = test_config(Timings {
+            lookup_timeout: Duration::from_secs(2),
+            ..Default::default()
+        })
+        .await?;
+        let ids = vec![topic_dht_id(
+            &TopicScheme::v2().topic_hash_32(b"self-check"),
+        )?];
+        let (state, _candidates) = DiscoveryState::new(
+            &config,
+            Vec::new(),
+            AddrBook::for_endpoint(&config.endpoint)?,
+        );
+
+        assert!(!verify_own_announcement(&dht, &ids, &config).await);
+        state.set_announcement_visible(false);
+        assert!(matches!(
+            state.announcement_status.get(),
+            AnnouncementStatus::NotVisible {
+                last_verified: None,
+                ..
+            }
+        ));
+
+        announce_all(&dht, &ids, &config.signing_key).await?;
+        assert!(verify_own_announcement(&dht, &ids, &config).await);
+        state.set_announcement_visible(true);
+        assert!(matches!(
+            state.announcement_status.get(),
+            AnnouncementStatus::Visible { .. }
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn invite_only_descriptors_refuse_candidates_without_a_membership() -> anyhow::Result<()>
+    {
+        let config = test_config(Timings::default()).await?;
+        let (state, _candidates) = DiscoveryState::new(
+            &config,
+            Vec::new(),
+            AddrBook::for_endpoint(&config.endpoint)?,
+        );
+        let descriptor = TopicDescriptor::new("team", config.endpoint.id());
+        assert!(!state.refuses_candidates(&config));
+
+        state.descriptor.set(Some(descriptor.clone())).ok();
+        assert!(!state.refuses_candidates(&config));
+
+        let invite_only = TopicFlags {
+            invite_only: true,
+            ..Default::default()
+        };
+        state
+            .descriptor
+            .set(Some(descriptor.with_flags(invite_only)))
+            .ok();
+        assert!(state.refuses_candidates(&config));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn only_candidates_with_a_published_proof_are_selected() -> anyhow::Result<()> {
+        let (_testnet, dht) = testnet_dht().await?;
+        let dht = Arc::new(dht);
+        let infohash = TopicScheme::v2().topic_infohash(b"pow");
+        let candidate = |key: &SigningKey| -> anyhow::Result<PeerCandidate> {
+            Ok(PeerCandidate {
+                peer: EndpointId::from_bytes(key.verifying_key().as_bytes())?,
+                timestamp: 0,
+            })
+        };
+
+        let (worker, weak, silent) = (
+            SigningKey::from_bytes(&rand::random()),
+            SigningKey::from_bytes(&rand::random()),
+            SigningKey::from_bytes(&rand::random()),
+        );
+        let worker_id = candidate(&worker)?.peer;
+        let proof = ProofOfWork::solve(&infohash, &worker_id, 16);
+        publish_proof(&dht, &worker, &infohash, &proof).await?;
+        // a proof for another endpoint doesn't count
+        publish_proof(&dht, &weak, &infohash, &proof).await?;
+
+        let candidates = vec![candidate(&worker)?, candidate(&weak)?, candidate(&silent)?];
+        let mut proofs = ProofChecks::new(Duration::from_secs(300));
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+        let selected = proven_candidates(
+            &dht,
+            candidates.clone(),
+            infohash,
+            16,
+            &mut proofs,
+            deadline,
+        )
+        .await;
+        assert_eq!(
+            selected.iter().map(|c| c.peer).collect::<Vec<_>>(),
+            vec![worker_id]
+        );
+        assert!(proofs.is_proven(&worker_id, 16));
+
+        // misses and weak proofs wait for the retry interval, verified proofs aren't fetched again
+        for candidate in &candidates[1..] {
+            assert!(!proofs.may_check(&candidate.peer, 16));
+        }
+        let silent_proof = ProofOfWork::solve(&infohash, &candidates[2].peer, 16);
+        publish_proof(&dht, &silent, &infohash, &silent_proof).await?;
+        let selected = proven_candidates(
+            &dht,
+            candidates,
+            infohash,
+            16,
+            &mut proofs,
+            tokio::time::Instant::now() + Duration::from_secs(10),
+        )
+        .await;
+        assert_eq!(
+            selected.iter().map(|c| c.peer).collect::<Vec<_>>(),
+            vec![worker_id]
+        );
+        Ok(())
+    }
 }
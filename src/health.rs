@@ -0,0 +1,95 @@
+use serde::Serialize;
+
+/// Whether the DHT around our topic supports signed peer announcements (draft BEP PR #174).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub enum DhtHealth {
+    /// No lookup finished after our first announce yet
+    #[default]
+    Unknown,
+    /// Lookups return signed peer records
+    Healthy,
+    /// Nodes accept our announcements but nobody else is on the topic
+    Empty,
+    /// Nodes respond but none return signed peers or accept our announcements
+    Unsupported,
+    /// No DHT node responded
+    Unreachable,
+}
+
+impl DhtHealth {
+    /// True if the DHT can't be used for this topic and the other sources should be tried.
+    pub fn is_degraded(&self) -> bool {
+        matches!(self, DhtHealth::Unsupported | DhtHealth::Unreachable)
+    }
+
+    /// `announce_accepted` is None until the first announce finished.
+    pub(crate) fn classify(lookup: &LookupDiagnostics, announce_accepted: Option<bool>) -> Self {
+        let Some(announce_accepted) = announce_accepted else {
+            return DhtHealth::Unknown;
+        };
+        if lookup.responding_nodes == 0 {
+            DhtHealth::Unreachable
+        } else if lookup.supporting_nodes == 0 {
+            DhtHealth::Unsupported
+        } else if lookup.records > 0 {
+            DhtHealth::Healthy
+        } else if announce_accepted {
+            DhtHealth::Empty
+        } else {
+            DhtHealth::Unsupported
+        }
+    }
+}
+
+/// Node and record counts of one discovery lookup.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct LookupDiagnostics {
+    /// Nodes in our routing table when the lookup finished
+    pub responding_nodes: usize,
+    /// Nodes in our routing table of nodes supporting signed peers
+    pub supporting_nodes: usize,
+    /// Records returned, including stale and duplicate ones
+    pub records: usize,
+    /// Fresh records of other peers, deduplicated
+    pub fresh_records: usize,
+    pub stale_records: usize,
+    /// Records further in the future than the allowed clock skew
+    pub future_records: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_separates_empty_unsupported_and_unreachable() {
+        let lookup = |responding_nodes, supporting_nodes, records| LookupDiagnostics {
+            responding_nodes,
+            supporting_nodes,
+            records,
+            ..Default::default()
+        };
+        assert_eq!(
+            DhtHealth::classify(&lookup(0, 0, 0), None),
+            DhtHealth::Unknown
+        );
+        assert_eq!(
+            DhtHealth::classify(&lookup(40, 8, 3), Some(false)),
+            DhtHealth::Healthy
+        );
+        assert_eq!(
+            DhtHealth::classify(&lookup(0, 0, 0), Some(true)),
+            DhtHealth::Unreachable
+        );
+        assert_eq!(
+            DhtHealth::classify(&lookup(40, 8, 0), Some(true)),
+            DhtHealth::Empty
+        );
+        assert_eq!(
+            DhtHealth::classify(&lookup(40, 0, 0), Some(true)),
+            DhtHealth::Unsupported
+        );
+        assert!(DhtHealth::Unsupported.is_degraded());
+        assert!(!DhtHealth::Empty.is_degraded());
+    }
+}
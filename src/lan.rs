@@ -0,0 +1,187 @@
+use std::{
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    time::{Duration, SystemTime},
+};
+
+use ed25519_dalek::{Signer, SigningKey, VerifyingKey};
+use iroh::EndpointId;
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+
+/// Domain separator for signed LAN announcements.
+const LAN_SIGNATURE_DOMAIN: &[u8] = b"/iroh/topic-discovery/lan/v1";
+
+/// Max size of a LAN announcement datagram.
+const MAX_PACKET_SIZE: usize = 1200;
+
+/// Multicast discovery on the local network segment, for topics without DHT access.
+#[derive(Debug, Clone)]
+pub struct LanDiscoveryConfig {
+    /// Multicast group and port (default: 239.255.77.77:7747)
+    pub group: SocketAddrV4,
+    /// Local interface to join the group on, unspecified lets the OS pick (default: 0.0.0.0)
+    pub interface: Ipv4Addr,
+    /// How often we multicast our announcement (default: 5s)
+    pub announce_interval: Duration,
+    /// Max age of a received announcement (default: 30s)
+    pub max_age: Duration,
+    /// Receive multicast sent from this host, needed for several nodes on one machine (default: true)
+    pub multicast_loop: bool,
+}
+
+impl Default for LanDiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            group: SocketAddrV4::new(Ipv4Addr::new(239, 255, 77, 77), 7747),
+            interface: Ipv4Addr::UNSPECIFIED,
+            announce_interval: Duration::from_secs(5),
+            max_age: Duration::from_secs(30),
+            multicast_loop: true,
+        }
+    }
+}
+
+/// A verified announcement of another node on the local network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LanAnnouncement {
+    pub infohash: [u8; 20],
+    pub endpoint_id: EndpointId,
+    /// Direct addresses the node is reachable on
+    pub direct_addrs: Vec<SocketAddr>,
+    /// Microseconds since the unix epoch
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AnnouncementBody {
+    infohash: [u8; 20],
+    endpoint_id: [u8; 32],
+    direct_addrs: Vec<SocketAddr>,
+    timestamp: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AnnouncementPacket {
+    body: AnnouncementBody,
+    /// ed25519 signature over the domain separator and the postcard encoded body
+    signature: Vec<u8>,
+}
+
+impl AnnouncementBody {
+    fn signing_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        let mut bytes = LAN_SIGNATURE_DOMAIN.to_vec();
+        bytes.extend(postcard::to_stdvec(self)?);
+        Ok(bytes)
+    }
+}
+
+/// Signed multicast announcements of `EndpointId`s under topic infohashes.
+#[derive(Debug)]
+pub struct LanDiscovery {
+    socket: UdpSocket,
+    config: LanDiscoveryConfig,
+    signing_key: SigningKey,
+}
+
+impl LanDiscovery {
+    /// Joins the multicast group. Several instances can bind the same group on one host.
+    pub fn bind(config: LanDiscoveryConfig, signing_key: SigningKey) -> anyhow::Result<Self> {
+        use socket2::{Domain, Protocol, Socket, Type};
+
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+        socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, config.group.port())).into())?;
+        socket.join_multicast_v4(config.group.ip(), &config.interface)?;
+        socket.set_multicast_loop_v4(config.multicast_loop)?;
+        socket.set_nonblocking(true)?;
+
+        let socket = UdpSocket::from_std(std::net::UdpSocket::from(socket))?;
+        Ok(Self {
+            socket,
+            config,
+            signing_key,
+        })
+    }
+
+    pub fn config(&self) -> &LanDiscoveryConfig {
+        &self.config
+    }
+
+    /// Multicast a signed announcement of our key under `infohash`.
+    pub async fn announce(
+        &self,
+        infohash: [u8; 20],
+        direct_addrs: Vec<SocketAddr>,
+    ) -> anyhow::Result<()> {
+        let body = AnnouncementBody {
+            infohash,
+            endpoint_id: self.signing_key.verifying_key().to_bytes(),
+            direct_addrs,
+            timestamp: now_micros(),
+        };
+        let signature = self.signing_key.sign(&body.signing_bytes()?);
+        let packet = postcard::to_stdvec(&AnnouncementPacket {
+            body,
+            signature: signature.to_bytes().to_vec(),
+        })?;
+        anyhow::ensure!(
+            packet.len() <= MAX_PACKET_SIZE,
+            "LAN announcement too large ({} bytes)",
+            packet.len()
+        );
+        self.socket
+            .send_to(&packet, SocketAddr::V4(self.config.group))
+            .await?;
+        Ok(())
+    }
+
+    /// Next fresh, correctly signed announcement of another node.
+    ///
+    /// Invalid, stale and our own packets are skipped.
+    pub async fn recv(&self) -> anyhow::Result<LanAnnouncement> {
+        let mut buf = [0u8; MAX_PACKET_SIZE];
+        let own_key = self.signing_key.verifying_key().to_bytes();
+        loop {
+            let (len, from) = self.socket.recv_from(&mut buf).await?;
+            match self.verify(&buf[..len]) {
+                Ok(announcement) if *announcement.endpoint_id.as_bytes() != own_key => {
+                    return Ok(announcement);
+                }
+                Ok(_) => {}
+                Err(e) => tracing::debug!("lan_discovery: dropping packet from {from}: {e}"),
+            }
+        }
+    }
+
+    fn verify(&self, packet: &[u8]) -> anyhow::Result<LanAnnouncement> {
+        let packet: AnnouncementPacket = postcard::from_bytes(packet)?;
+        let key = VerifyingKey::from_bytes(&packet.body.endpoint_id)?;
+        let signature = ed25519_dalek::Signature::from_slice(&packet.signature)?;
+        key.verify_strict(&packet.body.signing_bytes()?, &signature)?;
+
+        let now = now_micros();
+        let age = Duration::from_micros(now.saturating_sub(packet.body.timestamp));
+        let skew = Duration::from_micros(packet.body.timestamp.saturating_sub(now));
+        anyhow::ensure!(age <= self.config.max_age, "stale announcement ({age:?})");
+        anyhow::ensure!(
+            skew <= self.config.max_age,
+            "announcement from the future ({skew:?})"
+        );
+
+        Ok(LanAnnouncement {
+            infohash: packet.body.infohash,
+            endpoint_id: iroh::PublicKey::from_verifying_key(key),
+            direct_addrs: packet.body.direct_addrs,
+            timestamp: packet.body.timestamp,
+        })
+    }
+}
+
+fn now_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64
+}
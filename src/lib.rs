@@ -1,3 +1,51 @@
+mod addr;
+mod descriptor;
+mod diagnostics;
 mod gossip;
+mod health;
+mod lan;
+mod membership;
+mod metrics;
+mod partition;
+mod policy;
+mod population;
+mod pow;
+mod schedule;
+mod selector;
+mod source;
+#[cfg(test)]
+mod test_utils;
+mod ticket;
+mod topic;
 
-pub use gossip::{TopicDiscoveryConfig, TopicDiscoveryExt, TopicDiscoveryHandle};
+pub use addr::DhtAddressLookup;
+pub use descriptor::{TopicDescriptor, TopicFlags};
+pub use diagnostics::{
+    AnnounceReport, AnnounceResult, DiagnosticsReport, DiscoveryPhase, LookupReport, PeerOutcome,
+    PeerReport,
+};
+pub use gossip::{
+    AnnouncementStatus, JoinTimeoutError, TopicDiscoveryConfig, TopicDiscoveryExt,
+    TopicDiscoveryHandle,
+};
+pub use health::{DhtHealth, LookupDiagnostics};
+pub use lan::{LanAnnouncement, LanDiscovery, LanDiscoveryConfig};
+pub use membership::{
+    MEMBERSHIP_ALPN, Membership, MembershipCapability, MembershipGate, MembershipHooks,
+};
+pub use metrics::DiscoveryMetrics;
+pub use partition::{MEMBERS_ALPN, PartitionHealing};
+pub use policy::{AnnounceMode, ClockErrorBehavior, RecordFreshness, StalenessPolicy, Timings};
+pub use population::{EstimateConfidence, PopulationEstimate};
+pub use pow::ProofOfWork;
+pub use schedule::DiscoverySchedule;
+pub use selector::{
+    NewestSelector, PeerCandidate, PeerSelection, PeerSelector, RandomSelector, RoundRobinSelector,
+    WeightedSuccessSelector,
+};
+pub use source::{
+    ChannelSource, DiscoveredPeer, DiscoverySource, PeerCache, SourceKind, SourcePolicy,
+    StaticSource,
+};
+pub use ticket::{MAX_TICKET_BOOTSTRAP, TopicTicket};
+pub use topic::{TOPIC_DOMAIN_V2, TopicScheme, shard_for_key, shard_infohash};
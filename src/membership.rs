@@ -0,0 +1,868 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant, SystemTime},
+};
+
+use dht::async_dht::AsyncDht;
+use ed25519_dalek::{Signer, SigningKey, VerifyingKey};
+use iroh::{
+    Endpoint, EndpointId,
+    endpoint::{
+        AfterHandshakeOutcome, BeforeConnectOutcome, Connection, EndpointHooks, VarInt,
+        WeakConnectionHandle,
+    },
+    protocol::{AcceptError, ProtocolHandler},
+};
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+use tokio::sync::Semaphore;
+
+use crate::pow::ProofOfWork;
+
+/// ALPN of the pre-join membership handshake.
+pub const MEMBERSHIP_ALPN: &[u8] = b"/iroh-topic-tracker/membership/1";
+
+/// Domain separator for capability signatures.
+const CAPABILITY_DOMAIN: &[u8] = b"/iroh/topic-discovery/capability/v1";
+
+/// Domain separator of the revocation list salt.
+const REVOCATION_DOMAIN: &[u8] = b"/iroh/topic-discovery/revocations/v1";
+
+/// Max size of a BEP44 value, about 30 revoked members.
+const MAX_REVOCATION_VALUE: usize = 1000;
+
+/// How long the gate waits for the handshake it dials back to unknown peers.
+const GATE_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the gate waits before dialing back to the same unknown peer again.
+const GATE_DIAL_BACK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Handshakes the gate dials back at the same time, further unknown peers are refused.
+const MAX_GATE_DIAL_BACKS: usize = 8;
+
+/// Max size of a handshake message.
+const MAX_HANDSHAKE_MESSAGE: usize = 1024;
+
+/// Close code for connections refused by the admission handshake or gate.
+const NOT_A_MEMBER: u32 = 403;
+
+/// Owner-signed permission for `member` to join the topic with `infohash`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MembershipCapability {
+    pub infohash: [u8; 20],
+    pub member: EndpointId,
+    /// Unix seconds after which the capability is no longer accepted
+    pub expires_at: u64,
+    /// ed25519 signature of the owner over the domain separator and the fields above
+    signature: Vec<u8>,
+}
+
+#[derive(Serialize)]
+struct CapabilityBody<'a> {
+    infohash: &'a [u8; 20],
+    member: &'a [u8; 32],
+    expires_at: u64,
+}
+
+impl MembershipCapability {
+    /// Issue a capability, signed with the topic owner's key.
+    pub fn issue(
+        owner: &SigningKey,
+        infohash: [u8; 20],
+        member: EndpointId,
+        expires_at: SystemTime,
+    ) -> anyhow::Result<Self> {
+        let expires_at = unix_secs(expires_at);
+        let body = signing_bytes(&infohash, &member, expires_at)?;
+        Ok(Self {
+            infohash,
+            member,
+            expires_at,
+            signature: owner.sign(&body).to_bytes().to_vec(),
+        })
+    }
+
+    /// Checks signature, topic and expiry.
+    pub fn verify(&self, owner: &EndpointId, infohash: &[u8; 20]) -> anyhow::Result<()> {
+        anyhow::ensure!(self.infohash == *infohash, "capability for another topic");
+        anyhow::ensure!(
+            unix_secs(SystemTime::now()) < self.expires_at,
+            "capability expired"
+        );
+        let key = VerifyingKey::from_bytes(owner.as_bytes())?;
+        let signature = ed25519_dalek::Signature::from_slice(&self.signature)?;
+        key.verify_strict(
+            &signing_bytes(&self.infohash, &self.member, self.expires_at)?,
+            &signature,
+        )?;
+        Ok(())
+    }
+}
+
+fn signing_bytes(
+    infohash: &[u8; 20],
+    member: &EndpointId,
+    expires_at: u64,
+) -> anyhow::Result<Vec<u8>> {
+    let mut bytes = CAPABILITY_DOMAIN.to_vec();
+    bytes.extend(postcard::to_stdvec(&CapabilityBody {
+        infohash,
+        member: member.as_bytes(),
+        expires_at,
+    })?);
+    Ok(bytes)
+}
+
+/// Message both sides send during the handshake.
+#[derive(Debug, Serialize, Deserialize)]
+struct Hello {
+    capability: Option<MembershipCapability>,
+    proof_of_work: Option<ProofOfWork>,
+}
+
+/// Admission control for invite-only or proof-of-work topics.
+///
+/// Peers exchange capabilities and proofs over `MEMBERSHIP_ALPN` before discovery
+/// dials them via gossip. Register the handshake and wrap gossip in the gate so strangers
+/// can't connect. The gate dials the handshake back to peers it doesn't know yet, so
+/// members introduced by gossip itself or dialed as bootstrap nodes get through.
+/// A gossip connection carries every topic, keep gated topics on their own endpoint.
+/// The hooks let revocations close the gossip connections we dialed, the gate only
+/// sees the ones we accepted:
+///
+/// ```ignore
+/// let endpoint = Endpoint::builder(presets::N0)
+///     .hooks(membership.hooks(iroh_gossip::ALPN))
+///     .bind()
+///     .await?;
+/// Router::builder(endpoint)
+///     .accept(MEMBERSHIP_ALPN, membership.clone())
+///     .accept(iroh_gossip::ALPN, membership.gate(gossip.clone()))
+///     .spawn();
+/// ```
+#[derive(Debug, Clone)]
+pub struct Membership {
+    infohash: [u8; 20],
+    /// Owner whose capabilities are required, None for open topics
+    owner: Option<EndpointId>,
+    capability: Option<MembershipCapability>,
+    /// Leading zero bits required from peers' proofs, 0 disables
+    pow_difficulty: u8,
+    proof_of_work: Option<ProofOfWork>,
+    inner: Arc<Mutex<MembershipState>>,
+    /// Used by the gate to dial handshakes back, set by the subscription
+    endpoint: Arc<OnceLock<Endpoint>>,
+    /// Permits for concurrent handshakes dialed back by the gate
+    dial_backs: Arc<Semaphore>,
+}
+
+#[derive(Debug, Default)]
+struct MembershipState {
+    revoked: HashSet<EndpointId>,
+    /// Latest revocation list published by the owner
+    published_revoked: HashSet<EndpointId>,
+    /// Difficulty the owner announced in the topic descriptor
+    announced_pow: u8,
+    /// Peers that presented a valid capability, with its expiry
+    admitted: HashMap<EndpointId, u64>,
+    /// Gated connections, closed when their peer is revoked
+    connections: HashMap<EndpointId, Vec<WeakConnectionHandle>>,
+    /// When the gate last dialed a handshake back to a peer
+    dialed_back: HashMap<EndpointId, Instant>,
+}
+
+impl MembershipState {
+    fn is_revoked(&self, peer: &EndpointId) -> bool {
+        self.revoked.contains(peer) || self.published_revoked.contains(peer)
+    }
+
+    fn track(&mut self, connection: &Connection) {
+        self.connections.retain(|_, handles| {
+            handles.retain(is_open);
+            !handles.is_empty()
+        });
+        self.connections
+            .entry(connection.remote_id())
+            .or_default()
+            .push(connection.weak_handle());
+    }
+
+    /// Close the gated connections of `peer`.
+    fn evict(&mut self, peer: &EndpointId) {
+        for handle in self.connections.remove(peer).unwrap_or_default() {
+            if let Some(connection) = handle.upgrade() {
+                tracing::debug!(
+                    "membership: closing connection of revoked {}",
+                    peer.fmt_short()
+                );
+                connection.close(VarInt::from_u32(NOT_A_MEMBER), b"revoked");
+            }
+        }
+    }
+}
+
+fn is_open(handle: &WeakConnectionHandle) -> bool {
+    handle
+        .upgrade()
+        .is_some_and(|connection| connection.close_reason().is_none())
+}
+
+impl Membership {
+    /// Invite-only topic, `capability` is our own, issued by `owner`.
+    pub fn new(owner: EndpointId, capability: MembershipCapability) -> Self {
+        let infohash = capability.infohash;
+        Self {
+            owner: Some(owner),
+            capability: Some(capability),
+            ..Self::open(infohash)
+        }
+    }
+
+    /// Topic anyone may join, combine with `with_proof_of_work`.
+    pub fn open(infohash: [u8; 20]) -> Self {
+        Self {
+            infohash,
+            owner: None,
+            capability: None,
+            pow_difficulty: 0,
+            proof_of_work: None,
+            inner: Arc::new(Mutex::new(MembershipState::default())),
+            endpoint: Arc::new(OnceLock::new()),
+            dial_backs: Arc::new(Semaphore::new(MAX_GATE_DIAL_BACKS)),
+        }
+    }
+
+    /// Require proofs with `difficulty` leading zero bits from peers. `proof` is
+    /// ours, see `ProofOfWork::solve`.
+    pub fn with_proof_of_work(mut self, difficulty: u8, proof: ProofOfWork) -> Self {
+        self.pow_difficulty = difficulty;
+        self.proof_of_work = Some(proof);
+        self
+    }
+
+    pub fn with_revoked(self, revoked: impl IntoIterator<Item = EndpointId>) -> Self {
+        for member in revoked {
+            self.revoke(member);
+        }
+        self
+    }
+
+    pub fn owner(&self) -> Option<EndpointId> {
+        self.owner
+    }
+
+    pub fn infohash(&self) -> [u8; 20] {
+        self.infohash
+    }
+
+    /// Leading zero bits required from peers, the higher of ours and the one the
+    /// owner announced in the topic descriptor.
+    pub fn pow_difficulty(&self) -> u8 {
+        let announced = self.inner.lock().expect("poisoned").announced_pow;
+        self.pow_difficulty.max(announced)
+    }
+
+    pub fn proof_of_work(&self) -> Option<ProofOfWork> {
+        self.proof_of_work
+    }
+
+    /// Require the difficulty the owner announced in `TopicFlags::pow_difficulty`.
+    pub(crate) fn require_announced_pow(&self, difficulty: u8) {
+        self.inner.lock().expect("poisoned").announced_pow = difficulty;
+    }
+
+    /// Stop accepting `member`'s capability, existing admissions are dropped and its
+    /// gated connections closed.
+    ///
+    /// Local only, the owner shares revocations with
+    /// `TopicDiscoveryHandle::publish_revocations`.
+    pub fn revoke(&self, member: EndpointId) {
+        let mut inner = self.inner.lock().expect("poisoned");
+        inner.admitted.remove(&member);
+        inner.revoked.insert(member);
+        inner.evict(&member);
+    }
+
+    /// True if `peer` completed a handshake and its capability, if required, is still valid.
+    pub fn is_admitted(&self, peer: &EndpointId) -> bool {
+        let inner = self.inner.lock().expect("poisoned");
+        !inner.is_revoked(peer)
+            && inner
+                .admitted
+                .get(peer)
+                .is_some_and(|expires_at| unix_secs(SystemTime::now()) < *expires_at)
+    }
+
+    /// Wrap a protocol handler, refusing connections from peers that aren't admitted
+    /// and fail the handshake dialed back to them.
+    pub fn gate<P: ProtocolHandler>(&self, inner: P) -> MembershipGate<P> {
+        MembershipGate {
+            membership: self.clone(),
+            inner,
+        }
+    }
+
+    /// Endpoint hooks that track our own connections with `alpn` so revocations close
+    /// them too, and refuse dialing revoked peers.
+    pub fn hooks(&self, alpn: &[u8]) -> MembershipHooks {
+        MembershipHooks {
+            alpn: alpn.to_vec(),
+            inner: self.inner.clone(),
+        }
+    }
+
+    /// Replace the owner's published revocations, members missing from `revoked`
+    /// are admitted again after their next handshake.
+    pub(crate) fn apply_revocations(&self, revoked: impl IntoIterator<Item = EndpointId>) {
+        let mut inner = self.inner.lock().expect("poisoned");
+        inner.published_revoked = revoked.into_iter().collect();
+        let MembershipState {
+            admitted,
+            published_revoked,
+            ..
+        } = &mut *inner;
+        admitted.retain(|member, _| !published_revoked.contains(member));
+        for member in inner.published_revoked.clone() {
+            inner.evict(&member);
+        }
+    }
+
+    pub(crate) fn bind(&self, endpoint: &Endpoint) {
+        let _ = self.endpoint.set(endpoint.clone());
+    }
+
+    fn is_revoked(&self, peer: &EndpointId) -> bool {
+        self.inner.lock().expect("poisoned").is_revoked(peer)
+    }
+
+    /// Records a dial-back to `peer`, false if the last one was too recent.
+    fn may_dial_back(&self, peer: EndpointId) -> bool {
+        let mut inner = self.inner.lock().expect("poisoned");
+        let now = Instant::now();
+        inner
+            .dialed_back
+            .retain(|_, at| now.duration_since(*at) < GATE_DIAL_BACK_INTERVAL);
+        if inner.dialed_back.contains_key(&peer) {
+            return false;
+        }
+        inner.dialed_back.insert(peer, now);
+        true
+    }
+
+    fn admit(&self, peer: EndpointId, hello: &Hello) -> anyhow::Result<()> {
+        let mut expires_at = u64::MAX;
+        if let Some(owner) = &self.owner {
+            let capability = hello
+                .capability
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("missing capability"))?;
+            anyhow::ensure!(
+                capability.member == peer,
+                "capability issued to another peer"
+            );
+            capability.verify(owner, &self.infohash)?;
+            expires_at = capability.expires_at;
+        }
+        let pow_difficulty = self.pow_difficulty();
+        if pow_difficulty > 0 {
+            let proof = hello
+                .proof_of_work
+                .ok_or_else(|| anyhow::anyhow!("missing proof of work"))?;
+            anyhow::ensure!(
+                proof.verify(&self.infohash, &peer, pow_difficulty),
+                "insufficient proof of work"
+            );
+        }
+        let mut inner = self.inner.lock().expect("poisoned");
+        anyhow::ensure!(!inner.is_revoked(&peer), "peer revoked");
+        inner.admitted.insert(peer, expires_at);
+        Ok(())
+    }
+
+    fn hello(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(postcard::to_stdvec(&Hello {
+            capability: self.capability.clone(),
+            proof_of_work: self.proof_of_work,
+        })?)
+    }
+
+    /// Dial `peer`, exchange capabilities and admit it if its capability is valid.
+    pub(crate) async fn handshake(
+        &self,
+        endpoint: &Endpoint,
+        peer: EndpointId,
+    ) -> anyhow::Result<()> {
+        let connection = endpoint.connect(peer, MEMBERSHIP_ALPN).await?;
+        let (mut send, mut recv) = connection.open_bi().await?;
+        send.write_all(&self.hello()?).await?;
+        send.finish()?;
+        let response = recv.read_to_end(MAX_HANDSHAKE_MESSAGE).await?;
+        connection.close(VarInt::from_u32(0), b"done");
+
+        let hello: Hello = postcard::from_bytes(&response)?;
+        self.admit(peer, &hello)
+    }
+
+    async fn respond(&self, connection: &Connection) -> anyhow::Result<()> {
+        let peer = connection.remote_id();
+        let (mut send, mut recv) = connection.accept_bi().await?;
+        let request = recv.read_to_end(MAX_HANDSHAKE_MESSAGE).await?;
+        let hello: Hello = postcard::from_bytes(&request)?;
+        self.admit(peer, &hello)?;
+
+        send.write_all(&self.hello()?).await?;
+        send.finish()?;
+        connection.closed().await;
+        Ok(())
+    }
+}
+
+impl ProtocolHandler for Membership {
+    async fn accept(&self, connection: Connection) -> Result<(), AcceptError> {
+        if let Err(e) = self.respond(&connection).await {
+            tracing::debug!(
+                "membership: handshake with {} failed: {e}",
+                connection.remote_id().fmt_short()
+            );
+            connection.close(VarInt::from_u32(NOT_A_MEMBER), b"not a member");
+        }
+        Ok(())
+    }
+}
+
+/// Protocol handler that only passes connections of admitted members to `inner`.
+#[derive(Debug, Clone)]
+pub struct MembershipGate<P> {
+    membership: Membership,
+    inner: P,
+}
+
+impl<P: ProtocolHandler> ProtocolHandler for MembershipGate<P> {
+    async fn accept(&self, connection: Connection) -> Result<(), AcceptError> {
+        let peer = connection.remote_id();
+        if !self.membership.is_admitted(&peer) && !self.handshake_back(peer).await {
+            tracing::debug!("membership_gate: refusing {}", peer.fmt_short());
+            connection.close(VarInt::from_u32(NOT_A_MEMBER), b"not a member");
+            return Ok(());
+        }
+        self.membership
+            .inner
+            .lock()
+            .expect("poisoned")
+            .track(&connection);
+        self.inner.accept(connection).await
+    }
+
+    async fn shutdown(&self) {
+        self.inner.shutdown().await
+    }
+}
+
+impl<P> MembershipGate<P> {
+    /// Admit a peer that connected before we handshaked with it. Dial-backs are
+    /// limited per peer and in total, unknown peers cost us a connection each.
+    async fn handshake_back(&self, peer: EndpointId) -> bool {
+        let Some(endpoint) = self.membership.endpoint.get() else {
+            return false;
+        };
+        if self.membership.is_revoked(&peer) {
+            return false;
+        }
+        let Ok(_permit) = self.membership.dial_backs.try_acquire() else {
+            tracing::debug!(
+                "membership_gate: too many handshakes in flight, refusing {}",
+                peer.fmt_short()
+            );
+            return false;
+        };
+        if !self.membership.may_dial_back(peer) {
+            tracing::debug!(
+                "membership_gate: dialed back to {} recently",
+                peer.fmt_short()
+            );
+            return false;
+        }
+        match tokio::time::timeout(
+            GATE_HANDSHAKE_TIMEOUT,
+            self.membership.handshake(endpoint, peer),
+        )
+        .await
+        {
+            Ok(Ok(())) => true,
+            Ok(Err(e)) => {
+                tracing::debug!(
+                    "membership_gate: handshake with {} failed: {e}",
+                    peer.fmt_short()
+                );
+                false
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+/// Endpoint hooks of a `Membership`, see `Membership::hooks`.
+///
+/// Holds the membership state but not the endpoint, hooks are stored on the endpoint.
+#[derive(Debug, Clone)]
+pub struct MembershipHooks {
+    alpn: Vec<u8>,
+    inner: Arc<Mutex<MembershipState>>,
+}
+
+impl EndpointHooks for MembershipHooks {
+    async fn before_connect<'a>(
+        &'a self,
+        remote_addr: &'a iroh::EndpointAddr,
+        alpn: &'a [u8],
+    ) -> BeforeConnectOutcome {
+        if alpn == self.alpn
+            && self
+                .inner
+                .lock()
+                .expect("poisoned")
+                .is_revoked(&remote_addr.id)
+        {
+            return BeforeConnectOutcome::Reject;
+        }
+        BeforeConnectOutcome::Accept
+    }
+
+    async fn after_handshake<'a>(&'a self, connection: &'a Connection) -> AfterHandshakeOutcome {
+        if connection.alpn() == self.alpn.as_slice() {
+            let mut inner = self.inner.lock().expect("poisoned");
+            if inner.is_revoked(&connection.remote_id()) {
+                return AfterHandshakeOutcome::Reject {
+                    error_code: VarInt::from_u32(NOT_A_MEMBER),
+                    reason: b"revoked".to_vec(),
+                };
+            }
+            inner.track(connection);
+        }
+        AfterHandshakeOutcome::accept()
+    }
+}
+
+/// Owner-signed list of revoked members of a topic, the BEP44 value.
+#[derive(Debug, Serialize, Deserialize)]
+struct RevocationList {
+    revoked: Vec<EndpointId>,
+}
+
+/// BEP44 salt of the revocation list of the topic with `infohash`.
+fn revocation_salt(infohash: &[u8; 20]) -> [u8; 32] {
+    let mut hasher = sha2::Sha512::new();
+    hasher.update(REVOCATION_DOMAIN);
+    hasher.update(infohash);
+    hasher.finalize()[..32].try_into().expect("hashing failed")
+}
+
+/// Publish the revoked members of the topic with `infohash`, replacing the previous list.
+pub(crate) async fn publish_revocations(
+    dht: &AsyncDht,
+    owner_key: &SigningKey,
+    infohash: &[u8; 20],
+    revoked: &[EndpointId],
+) -> anyhow::Result<()> {
+    let value = postcard::to_stdvec(&RevocationList {
+        revoked: revoked.to_vec(),
+    })?;
+    anyhow::ensure!(
+        value.len() <= MAX_REVOCATION_VALUE,
+        "revocation list too large ({} bytes)",
+        value.len()
+    );
+    // newer lists win, micros since the epoch fit an i64 for a long time
+    let seq = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as i64;
+    let salt = revocation_salt(infohash);
+    let item = dht::MutableItem::new(owner_key, &value, seq, Some(&salt));
+    dht.put_mutable(item, None).await?;
+    Ok(())
+}
+
+/// Most recent revocation list signed by `owner`, None if none was published.
+pub(crate) async fn fetch_revocations(
+    dht: &AsyncDht,
+    owner: EndpointId,
+    infohash: &[u8; 20],
+) -> anyhow::Result<Option<Vec<EndpointId>>> {
+    let salt = revocation_salt(infohash);
+    let Some(item) = dht
+        .get_mutable_most_recent(owner.as_bytes(), Some(&salt))
+        .await
+    else {
+        return Ok(None);
+    };
+    let list: RevocationList = postcard::from_bytes(item.value())?;
+    Ok(Some(list.revoked))
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh::{SecretKey, protocol::Router};
+
+    use super::*;
+    use crate::test_utils::{endpoint, learn, testnet_dht};
+
+    const ECHO_ALPN: &[u8] = b"/iroh-topic-tracker/test/echo";
+
+    #[derive(Debug, Clone)]
+    struct Echo;
+
+    impl ProtocolHandler for Echo {
+        async fn accept(&self, connection: Connection) -> Result<(), AcceptError> {
+            let (mut send, mut recv) = connection.accept_bi().await?;
+            let message = recv.read_to_end(64).await.map_err(AcceptError::from_err)?;
+            send.write_all(&message)
+                .await
+                .map_err(AcceptError::from_err)?;
+            send.finish().map_err(AcceptError::from_err)?;
+            connection.closed().await;
+            Ok(())
+        }
+    }
+
+    struct Node {
+        endpoint: Endpoint,
+        membership: Membership,
+        _router: Router,
+    }
+
+    async fn node(membership: Membership) -> anyhow::Result<Node> {
+        Ok(serve(endpoint().await?, membership))
+    }
+
+    fn serve(endpoint: Endpoint, membership: Membership) -> Node {
+        membership.bind(&endpoint);
+        let router = crate::test_utils::serve(endpoint.clone(), |router| {
+            router
+                .accept(MEMBERSHIP_ALPN, membership.clone())
+                .accept(ECHO_ALPN, membership.gate(Echo))
+        });
+        Node {
+            endpoint,
+            membership,
+            _router: router,
+        }
+    }
+
+    async fn echo(from: &Node, to: &Node) -> anyhow::Result<Vec<u8>> {
+        let connection = from.endpoint.connect(to.endpoint.id(), ECHO_ALPN).await?;
+        let (mut send, mut recv) = connection.open_bi().await?;
+        send.write_all(b"hello").await?;
+        send.finish()?;
+        Ok(recv.read_to_end(64).await?)
+    }
+
+    /// Invite-only member node of the topic with `infohash`, with a capability from `owner`.
+    async fn member(owner: &SigningKey, infohash: [u8; 20]) -> anyhow::Result<Node> {
+        let owner_id = EndpointId::from_bytes(owner.verifying_key().as_bytes())?;
+        let endpoint = endpoint().await?;
+        let expires_at = SystemTime::now() + Duration::from_secs(3600);
+        let capability = MembershipCapability::issue(owner, infohash, endpoint.id(), expires_at)?;
+        Ok(serve(endpoint, Membership::new(owner_id, capability)))
+    }
+
+    #[tokio::test]
+    async fn handshake_admits_members_and_refuses_strangers() -> anyhow::Result<()> {
+        let owner = SigningKey::from_bytes(&rand::random());
+        let owner_id = EndpointId::from_bytes(owner.verifying_key().as_bytes())?;
+        let infohash = [5u8; 20];
+        let a = member(&owner, infohash).await?;
+        let b = member(&owner, infohash).await?;
+        learn(&a.endpoint, &b.endpoint)?;
+
+        a.membership.handshake(&a.endpoint, b.endpoint.id()).await?;
+        assert!(a.membership.is_admitted(&b.endpoint.id()));
+        assert!(b.membership.is_admitted(&a.endpoint.id()));
+
+        // a capability issued to someone else doesn't work
+        let borrowed = MembershipCapability::issue(
+            &owner,
+            infohash,
+            SecretKey::generate().public(),
+            SystemTime::now() + Duration::from_secs(3600),
+        )?;
+        let stranger = node(Membership::new(owner_id, borrowed)).await?;
+        learn(&stranger.endpoint, &b.endpoint)?;
+        assert!(
+            stranger
+                .membership
+                .handshake(&stranger.endpoint, b.endpoint.id())
+                .await
+                .is_err()
+        );
+        assert!(!b.membership.is_admitted(&stranger.endpoint.id()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn gate_handshakes_back_with_unknown_peers() -> anyhow::Result<()> {
+        let owner = SigningKey::from_bytes(&rand::random());
+        let infohash = [6u8; 20];
+        let a = member(&owner, infohash).await?;
+        let b = member(&owner, infohash).await?;
+        learn(&a.endpoint, &b.endpoint)?;
+        learn(&b.endpoint, &a.endpoint)?;
+
+        // a connects without a prior handshake, like a join forwarded by gossip
+        assert_eq!(echo(&a, &b).await?, b"hello");
+        assert!(b.membership.is_admitted(&a.endpoint.id()));
+
+        let stranger = node(Membership::open(infohash)).await?;
+        learn(&stranger.endpoint, &b.endpoint)?;
+        learn(&b.endpoint, &stranger.endpoint)?;
+        assert!(echo(&stranger, &b).await.is_err());
+        // the failed dial-back counts, the next connection is refused without one
+        assert!(!b.membership.may_dial_back(stranger.endpoint.id()));
+        Ok(())
+    }
+
+    /// Opens an echo connection from `from` to `to` and keeps it open.
+    async fn echo_connection(from: &Node, to: &Node) -> anyhow::Result<Connection> {
+        let connection = from.endpoint.connect(to.endpoint.id(), ECHO_ALPN).await?;
+        let (mut send, mut recv) = connection.open_bi().await?;
+        send.write_all(b"hello").await?;
+        send.finish()?;
+        recv.read_to_end(64).await?;
+        Ok(connection)
+    }
+
+    fn closed_as_revoked(connection: &Connection) -> bool {
+        matches!(
+            connection.close_reason(),
+            Some(iroh::endpoint::ConnectionError::ApplicationClosed(close))
+                if close.error_code == VarInt::from_u32(NOT_A_MEMBER)
+        )
+    }
+
+    #[tokio::test]
+    async fn revocations_close_gated_connections() -> anyhow::Result<()> {
+        let owner = SigningKey::from_bytes(&rand::random());
+        let infohash = [9u8; 20];
+        let a = member(&owner, infohash).await?;
+        let b = member(&owner, infohash).await?;
+        let c = member(&owner, infohash).await?;
+        for (from, to) in [(&a, &b), (&b, &a), (&c, &b), (&b, &c)] {
+            learn(&from.endpoint, &to.endpoint)?;
+        }
+        let revoked = echo_connection(&a, &b).await?;
+        let published = echo_connection(&c, &b).await?;
+
+        b.membership.revoke(a.endpoint.id());
+        tokio::time::timeout(Duration::from_secs(5), revoked.closed()).await?;
+        assert!(closed_as_revoked(&revoked));
+        assert!(published.close_reason().is_none());
+
+        b.membership.apply_revocations([c.endpoint.id()]);
+        tokio::time::timeout(Duration::from_secs(5), published.closed()).await?;
+        assert!(closed_as_revoked(&published));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn hooks_close_dialed_connections_of_revoked_members() -> anyhow::Result<()> {
+        let owner = SigningKey::from_bytes(&rand::random());
+        let infohash = [10u8; 20];
+        let owner_id = EndpointId::from_bytes(owner.verifying_key().as_bytes())?;
+        let secret_key = SecretKey::generate();
+        let expires_at = SystemTime::now() + Duration::from_secs(3600);
+        let capability =
+            MembershipCapability::issue(&owner, infohash, secret_key.public(), expires_at)?;
+        let membership = Membership::new(owner_id, capability);
+        let endpoint = Endpoint::builder(iroh::endpoint::presets::Minimal)
+            .secret_key(secret_key)
+            .hooks(membership.hooks(ECHO_ALPN))
+            .bind()
+            .await?;
+        let a = serve(endpoint, membership);
+        let b = member(&owner, infohash).await?;
+        learn(&a.endpoint, &b.endpoint)?;
+        learn(&b.endpoint, &a.endpoint)?;
+
+        let dialed = echo_connection(&a, &b).await?;
+        a.membership.revoke(b.endpoint.id());
+        tokio::time::timeout(Duration::from_secs(5), dialed.closed()).await?;
+        assert!(matches!(
+            dialed.close_reason(),
+            Some(iroh::endpoint::ConnectionError::LocallyClosed)
+        ));
+        assert!(echo(&a, &b).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn announced_pow_difficulty_is_enforced_in_the_handshake() -> anyhow::Result<()> {
+        let owner = SigningKey::from_bytes(&rand::random());
+        let infohash = [8u8; 20];
+        let a = member(&owner, infohash).await?;
+        let b = member(&owner, infohash).await?;
+        learn(&a.endpoint, &b.endpoint)?;
+
+        b.membership.require_announced_pow(8);
+        assert_eq!(b.membership.pow_difficulty(), 8);
+        assert!(
+            a.membership
+                .handshake(&a.endpoint, b.endpoint.id())
+                .await
+                .is_err()
+        );
+        assert!(!b.membership.is_admitted(&a.endpoint.id()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn published_revocations_replace_the_previous_list() -> anyhow::Result<()> {
+        let (_testnet, dht) = testnet_dht().await?;
+        let owner = SigningKey::from_bytes(&rand::random());
+        let owner_id = EndpointId::from_bytes(owner.verifying_key().as_bytes())?;
+        let infohash = [7u8; 20];
+        let a = member(&owner, infohash).await?;
+        let b = member(&owner, infohash).await?;
+        learn(&a.endpoint, &b.endpoint)?;
+        a.membership.handshake(&a.endpoint, b.endpoint.id()).await?;
+
+        assert_eq!(fetch_revocations(&dht, owner_id, &infohash).await?, None);
+        publish_revocations(&dht, &owner, &infohash, &[a.endpoint.id()]).await?;
+        let revoked = fetch_revocations(&dht, owner_id, &infohash)
+            .await?
+            .unwrap_or_default();
+        assert_eq!(revoked, vec![a.endpoint.id()]);
+        b.membership.apply_revocations(revoked);
+        assert!(!b.membership.is_admitted(&a.endpoint.id()));
+        assert!(
+            a.membership
+                .handshake(&a.endpoint, b.endpoint.id())
+                .await
+                .is_err()
+        );
+
+        // only the owner's list counts
+        let stranger = SigningKey::from_bytes(&rand::random());
+        publish_revocations(&dht, &stranger, &infohash, &[]).await?;
+        assert_eq!(
+            fetch_revocations(&dht, owner_id, &infohash).await?,
+            Some(vec![a.endpoint.id()])
+        );
+
+        publish_revocations(&dht, &owner, &infohash, &[]).await?;
+        b.membership.apply_revocations(
+            fetch_revocations(&dht, owner_id, &infohash)
+                .await?
+                .unwrap_or_default(),
+        );
+        a.membership.handshake(&a.endpoint, b.endpoint.id()).await?;
+        assert!(b.membership.is_admitted(&a.endpoint.id()));
+        Ok(())
+    }
+}
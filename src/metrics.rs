@@ -0,0 +1,293 @@
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use serde::Serialize;
+
+/// Counters maintained by the announce, discovery and connector tasks of one subscription.
+#[derive(Debug, Default)]
+pub(crate) struct MetricsCounters {
+    announces_ok: AtomicU64,
+    announces_failed: AtomicU64,
+    announces_timed_out: AtomicU64,
+    announces_skipped: AtomicU64,
+    lookups: AtomicU64,
+    lookup_duration_ms_total: AtomicU64,
+    last_lookup_duration_ms: AtomicU64,
+    records_seen: AtomicU64,
+    records_fresh: AtomicU64,
+    records_stale: AtomicU64,
+    records_from_future: AtomicU64,
+    connector_attempts: AtomicU64,
+    connector_successes: AtomicU64,
+    connector_timeouts: AtomicU64,
+    partitions_suspected: AtomicU64,
+    /// 0 until the first neighbor connected, otherwise millis since the subscription started + 1
+    time_to_first_peer_ms: AtomicU64,
+}
+
+/// Discovery metrics of one subscription, see `TopicDiscoveryHandle::metrics`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DiscoveryMetrics {
+    pub announces_ok: u64,
+    pub announces_failed: u64,
+    pub announces_timed_out: u64,
+    /// Rounds skipped by `AnnounceMode::Adaptive`
+    pub announces_skipped: u64,
+    pub lookups: u64,
+    pub lookup_duration_total: Duration,
+    pub last_lookup_duration: Duration,
+    /// All records returned by lookups, including duplicates and our own
+    pub records_seen: u64,
+    /// Fresh records of other peers, deduplicated per lookup
+    pub records_fresh: u64,
+    pub records_stale: u64,
+    pub records_from_future: u64,
+    pub connector_attempts: u64,
+    pub connector_successes: u64,
+    pub connector_timeouts: u64,
+    /// Digest exchanges where we knew less than half of a neighbor's members
+    pub partitions_suspected: u64,
+    pub time_to_first_peer: Option<Duration>,
+}
+
+/// Per lookup record counts, added to the metrics once the lookup finished.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct LookupCounts {
+    pub seen: u64,
+    pub fresh: u64,
+    pub stale: u64,
+    pub from_future: u64,
+}
+
+impl MetricsCounters {
+    pub(crate) fn announce_ok(&self) {
+        self.announces_ok.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn announce_failed(&self) {
+        self.announces_failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn announce_timed_out(&self) {
+        self.announces_timed_out.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn announce_skipped(&self) {
+        self.announces_skipped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn lookup_finished(&self, duration: Duration, counts: LookupCounts) {
+        let millis = duration.as_millis() as u64;
+        self.lookups.fetch_add(1, Ordering::Relaxed);
+        self.lookup_duration_ms_total
+            .fetch_add(millis, Ordering::Relaxed);
+        self.last_lookup_duration_ms
+            .store(millis, Ordering::Relaxed);
+        self.records_seen.fetch_add(counts.seen, Ordering::Relaxed);
+        self.records_fresh
+            .fetch_add(counts.fresh, Ordering::Relaxed);
+        self.records_stale
+            .fetch_add(counts.stale, Ordering::Relaxed);
+        self.records_from_future
+            .fetch_add(counts.from_future, Ordering::Relaxed);
+    }
+
+    pub(crate) fn connector_started(&self) {
+        self.connector_attempts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn connector_succeeded(&self, since_start: Duration) {
+        self.connector_successes.fetch_add(1, Ordering::Relaxed);
+        let millis = since_start.as_millis() as u64;
+        let _ = self.time_to_first_peer_ms.compare_exchange(
+            0,
+            millis.saturating_add(1),
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        );
+    }
+
+    pub(crate) fn connector_timed_out(&self) {
+        self.connector_timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn partition_suspected(&self) {
+        self.partitions_suspected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> DiscoveryMetrics {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        DiscoveryMetrics {
+            announces_ok: load(&self.announces_ok),
+            announces_failed: load(&self.announces_failed),
+            announces_timed_out: load(&self.announces_timed_out),
+            announces_skipped: load(&self.announces_skipped),
+            lookups: load(&self.lookups),
+            lookup_duration_total: Duration::from_millis(load(&self.lookup_duration_ms_total)),
+            last_lookup_duration: Duration::from_millis(load(&self.last_lookup_duration_ms)),
+            records_seen: load(&self.records_seen),
+            records_fresh: load(&self.records_fresh),
+            records_stale: load(&self.records_stale),
+            records_from_future: load(&self.records_from_future),
+            connector_attempts: load(&self.connector_attempts),
+            connector_successes: load(&self.connector_successes),
+            connector_timeouts: load(&self.connector_timeouts),
+            partitions_suspected: load(&self.partitions_suspected),
+            time_to_first_peer: match load(&self.time_to_first_peer_ms) {
+                0 => None,
+                millis => Some(Duration::from_millis(millis - 1)),
+            },
+        }
+    }
+}
+
+impl DiscoveryMetrics {
+    /// Share of announces that succeeded, None before the first announce finished.
+    pub fn announce_success_rate(&self) -> Option<f64> {
+        let total = self.announces_ok + self.announces_failed + self.announces_timed_out;
+        (total > 0).then(|| self.announces_ok as f64 / total as f64)
+    }
+
+    /// Render in the Prometheus text exposition format.
+    ///
+    /// `labels` are added to every sample, e.g. `&[("topic", "chat")]` when
+    /// exporting several subscriptions from one process.
+    pub fn to_prometheus(&self, labels: &[(&str, &str)]) -> String {
+        let mut out = String::new();
+        let mut family = |name: &str, kind: &str, help: &str, samples: &[Sample]| {
+            write_family(&mut out, labels, name, kind, help, samples)
+        };
+
+        family(
+            "announces_total",
+            "counter",
+            "DHT announces by result",
+            &[
+                (Some(("result", "ok")), self.announces_ok.to_string()),
+                (
+                    Some(("result", "failed")),
+                    self.announces_failed.to_string(),
+                ),
+                (
+                    Some(("result", "timeout")),
+                    self.announces_timed_out.to_string(),
+                ),
+                (
+                    Some(("result", "skipped")),
+                    self.announces_skipped.to_string(),
+                ),
+            ],
+        );
+        family(
+            "lookups_total",
+            "counter",
+            "DHT peer lookups",
+            &[(None, self.lookups.to_string())],
+        );
+        family(
+            "lookup_duration_seconds_total",
+            "counter",
+            "Time spent in DHT peer lookups",
+            &[(None, self.lookup_duration_total.as_secs_f64().to_string())],
+        );
+        family(
+            "last_lookup_duration_seconds",
+            "gauge",
+            "Duration of the most recent DHT peer lookup",
+            &[(None, self.last_lookup_duration.as_secs_f64().to_string())],
+        );
+        family(
+            "records_total",
+            "counter",
+            "Signed peer records returned by lookups by classification",
+            &[
+                (Some(("state", "seen")), self.records_seen.to_string()),
+                (Some(("state", "fresh")), self.records_fresh.to_string()),
+                (Some(("state", "stale")), self.records_stale.to_string()),
+                (
+                    Some(("state", "future")),
+                    self.records_from_future.to_string(),
+                ),
+            ],
+        );
+        family(
+            "connector_attempts_total",
+            "counter",
+            "Connection attempts to discovered peers",
+            &[(None, self.connector_attempts.to_string())],
+        );
+        family(
+            "connector_total",
+            "counter",
+            "Finished connection attempts to discovered peers by result",
+            &[
+                (
+                    Some(("result", "success")),
+                    self.connector_successes.to_string(),
+                ),
+                (
+                    Some(("result", "timeout")),
+                    self.connector_timeouts.to_string(),
+                ),
+            ],
+        );
+        family(
+            "partitions_suspected_total",
+            "counter",
+            "Member digest exchanges where we knew less than half of a neighbor's members",
+            &[(None, self.partitions_suspected.to_string())],
+        );
+        if let Some(time_to_first_peer) = self.time_to_first_peer {
+            family(
+                "time_to_first_peer_seconds",
+                "gauge",
+                "Time from subscription start to the first connected neighbor",
+                &[(None, time_to_first_peer.as_secs_f64().to_string())],
+            );
+        }
+        out
+    }
+}
+
+/// Optional extra label and the rendered value of one sample.
+type Sample<'a> = (Option<(&'a str, &'a str)>, String);
+
+fn write_family(
+    out: &mut String,
+    labels: &[(&str, &str)],
+    name: &str,
+    kind: &str,
+    help: &str,
+    samples: &[Sample],
+) {
+    let _ = writeln!(out, "# HELP iroh_topic_tracker_{name} {help}");
+    let _ = writeln!(out, "# TYPE iroh_topic_tracker_{name} {kind}");
+    for (extra, value) in samples {
+        let mut rendered = labels
+            .iter()
+            .map(|(key, value)| format!("{key}=\"{}\"", escape_label(value)))
+            .collect::<Vec<_>>();
+        if let Some((key, value)) = extra {
+            rendered.push(format!("{key}=\"{}\"", escape_label(value)));
+        }
+        if rendered.is_empty() {
+            let _ = writeln!(out, "iroh_topic_tracker_{name} {value}");
+        } else {
+            let _ = writeln!(
+                out,
+                "iroh_topic_tracker_{name}{{{}}} {value}",
+                rendered.join(",")
+            );
+        }
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
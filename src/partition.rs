@@ -0,0 +1,338 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use iroh::{
+    Endpoint, EndpointId,
+    endpoint::{Connection, VarInt},
+    protocol::{AcceptError, ProtocolHandler},
+};
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+
+use crate::membership::Membership;
+
+/// ALPN of the member digest exchange.
+pub const MEMBERS_ALPN: &[u8] = b"/iroh-topic-tracker/members/1";
+
+/// Domain separator of member digests.
+const DIGEST_DOMAIN: &[u8] = b"/iroh/topic-discovery/members/v1";
+
+/// Members sent back when digests differ.
+const MAX_MEMBERS_PER_RESPONSE: usize = 64;
+
+/// Max size of a digest request or response.
+const MAX_DIGEST_MESSAGE: usize = 4096;
+
+/// Share of a neighbor's members we have to know, below it we suspect a partition.
+const MIN_MEMBER_OVERLAP: f64 = 0.5;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DigestRequest {
+    infohash: [u8; 20],
+    digest: [u8; 32],
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DigestResponse {
+    digest: [u8; 32],
+    /// Sample of the responder's members, empty if the digests match
+    members: Vec<EndpointId>,
+}
+
+/// How the members of a neighbor compare to ours, see `PartitionHealing::exchange`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct MemberComparison {
+    /// Share of the neighbor's sampled members we know
+    pub overlap: f64,
+    /// Sampled members we don't know
+    pub unknown: Vec<EndpointId>,
+}
+
+impl MemberComparison {
+    /// True if we know too few of the neighbor's members to be in the same swarm,
+    /// digests also differ when a single member joined or left.
+    pub fn is_partition(&self) -> bool {
+        self.overlap < MIN_MEMBER_OVERLAP
+    }
+}
+
+/// Detects and bridges split swarms.
+///
+/// Every discovery round dials `samples` random fresh records that aren't
+/// neighbors, not only the newest announcers, and compares a digest of the
+/// members we know with a random neighbor over `MEMBERS_ALPN`. If they differ,
+/// members the neighbor knows and we don't are dialed as `SourceKind::PeerExchange`,
+/// a partition is only counted if we know less than half of its sampled members.
+/// With a `Membership` only admitted peers get an answer, subscriptions with a
+/// `membership` set it on their healing. Register the protocol so neighbors can ask us:
+///
+/// ```ignore
+/// Router::builder(endpoint)
+///     .accept(MEMBERS_ALPN, healing.clone())
+///     .spawn();
+/// ```
+#[derive(Debug, Clone)]
+pub struct PartitionHealing {
+    infohash: [u8; 20],
+    /// Random fresh records dialed per round (default: 2)
+    samples: usize,
+    /// Members with the last time we saw their record or connection
+    members: Arc<Mutex<HashMap<EndpointId, Instant>>>,
+    /// Our own id, part of the digest, set by the first discovery round
+    own_id: Arc<OnceLock<EndpointId>>,
+    /// Only admitted peers may list members if set
+    membership: Arc<OnceLock<Membership>>,
+}
+
+impl PartitionHealing {
+    pub fn new(infohash: [u8; 20]) -> Self {
+        Self {
+            infohash,
+            samples: 2,
+            members: Arc::new(Mutex::new(HashMap::new())),
+            own_id: Arc::new(OnceLock::new()),
+            membership: Arc::new(OnceLock::new()),
+        }
+    }
+
+    pub fn with_samples(mut self, samples: usize) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    /// Only answer peers admitted by `membership`. The first membership set wins,
+    /// it's shared with every clone.
+    pub fn with_membership(self, membership: Membership) -> Self {
+        self.restrict_to(membership);
+        self
+    }
+
+    pub(crate) fn restrict_to(&self, membership: Membership) {
+        let _ = self.membership.set(membership);
+    }
+
+    pub fn infohash(&self) -> [u8; 20] {
+        self.infohash
+    }
+
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    /// Members seen within the record max age, excluding us.
+    pub fn members(&self) -> Vec<EndpointId> {
+        self.members
+            .lock()
+            .expect("poisoned")
+            .keys()
+            .copied()
+            .collect()
+    }
+
+    /// Hash over the sorted members and us, equal on nodes that know the same members.
+    pub fn digest(&self) -> [u8; 32] {
+        member_digest(self.members().into_iter().chain(self.own_id.get().copied()))
+    }
+
+    /// Record `members` as seen now by `own_id` and forget members older than `window`.
+    pub(crate) fn observe(
+        &self,
+        own_id: EndpointId,
+        members: impl IntoIterator<Item = EndpointId>,
+        window: Duration,
+    ) {
+        let _ = self.own_id.set(own_id);
+        let now = Instant::now();
+        let mut known = self.members.lock().expect("poisoned");
+        for member in members {
+            if member != own_id {
+                known.insert(member, now);
+            }
+        }
+        known.retain(|_, last_seen| now.duration_since(*last_seen) <= window);
+    }
+
+    /// Compare digests with `peer`, and if they differ how many of the members it
+    /// sampled we know. None if the digests match.
+    pub(crate) async fn exchange(
+        &self,
+        endpoint: &Endpoint,
+        peer: EndpointId,
+    ) -> anyhow::Result<Option<MemberComparison>> {
+        let request = DigestRequest {
+            infohash: self.infohash,
+            digest: self.digest(),
+        };
+        let connection = endpoint.connect(peer, MEMBERS_ALPN).await?;
+        let (mut send, mut recv) = connection.open_bi().await?;
+        send.write_all(&postcard::to_stdvec(&request)?).await?;
+        send.finish()?;
+        let response = recv.read_to_end(MAX_DIGEST_MESSAGE).await?;
+        connection.close(VarInt::from_u32(0), b"done");
+
+        let response: DigestResponse = postcard::from_bytes(&response)?;
+        if response.digest == request.digest {
+            return Ok(None);
+        }
+        let known = self.members.lock().expect("poisoned");
+        let sampled: Vec<EndpointId> = response
+            .members
+            .into_iter()
+            .filter(|member| *member != endpoint.id())
+            .collect();
+        let unknown: Vec<EndpointId> = sampled
+            .iter()
+            .filter(|member| !known.contains_key(*member))
+            .copied()
+            .collect();
+        let overlap = if sampled.is_empty() {
+            1.0
+        } else {
+            (sampled.len() - unknown.len()) as f64 / sampled.len() as f64
+        };
+        Ok(Some(MemberComparison { overlap, unknown }))
+    }
+
+    async fn respond(&self, connection: &Connection) -> anyhow::Result<()> {
+        if let Some(membership) = self.membership.get() {
+            anyhow::ensure!(
+                membership.is_admitted(&connection.remote_id()),
+                "not a member"
+            );
+        }
+        let (mut send, mut recv) = connection.accept_bi().await?;
+        let request = recv.read_to_end(MAX_DIGEST_MESSAGE).await?;
+        let request: DigestRequest = postcard::from_bytes(&request)?;
+        anyhow::ensure!(
+            request.infohash == self.infohash,
+            "digest for another topic"
+        );
+
+        let digest = self.digest();
+        let mut members = self.members();
+        if digest == request.digest {
+            members.clear();
+        } else {
+            members.sort_by_cached_key(|_| rand::random::<u64>());
+            members.truncate(MAX_MEMBERS_PER_RESPONSE);
+        }
+        send.write_all(&postcard::to_stdvec(&DigestResponse { digest, members })?)
+            .await?;
+        send.finish()?;
+        connection.closed().await;
+        Ok(())
+    }
+}
+
+impl ProtocolHandler for PartitionHealing {
+    async fn accept(&self, connection: Connection) -> Result<(), AcceptError> {
+        if let Err(e) = self.respond(&connection).await {
+            tracing::debug!(
+                "partition_healing: digest exchange with {} failed: {e}",
+                connection.remote_id().fmt_short()
+            );
+        }
+        Ok(())
+    }
+}
+
+fn member_digest(members: impl IntoIterator<Item = EndpointId>) -> [u8; 32] {
+    let mut members = members.into_iter().collect::<Vec<_>>();
+    members.sort_by_key(|member| *member.as_bytes());
+    let mut hasher = sha2::Sha512::new();
+    hasher.update(DIGEST_DOMAIN);
+    for member in &members {
+        hasher.update(member.as_bytes());
+    }
+    hasher.finalize()[..32].try_into().expect("hashing failed")
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh::{SecretKey, protocol::Router};
+
+    use super::*;
+    use crate::test_utils::learn;
+
+    const WINDOW: Duration = Duration::from_secs(600);
+
+    async fn node(healing: PartitionHealing) -> anyhow::Result<(Endpoint, Router)> {
+        crate::test_utils::node(|router| router.accept(MEMBERS_ALPN, healing)).await
+    }
+
+    #[tokio::test]
+    async fn nodes_that_know_the_same_members_have_equal_digests() -> anyhow::Result<()> {
+        let infohash = [3u8; 20];
+        let (a, b) = (
+            PartitionHealing::new(infohash),
+            PartitionHealing::new(infohash),
+        );
+        let (endpoint_a, _router_a) = node(a.clone()).await?;
+        let (endpoint_b, _router_b) = node(b.clone()).await?;
+        learn(&endpoint_a, &endpoint_b)?;
+
+        let other = SecretKey::generate().public();
+        a.observe(endpoint_a.id(), [endpoint_b.id(), other], WINDOW);
+        b.observe(endpoint_b.id(), [endpoint_a.id(), other], WINDOW);
+        assert_eq!(a.digest(), b.digest());
+        assert_eq!(a.exchange(&endpoint_a, endpoint_b.id()).await?, None);
+
+        // b knows a member we don't, we still know most of its members
+        let unknown = SecretKey::generate().public();
+        b.observe(endpoint_b.id(), [unknown], WINDOW);
+        let comparison = a
+            .exchange(&endpoint_a, endpoint_b.id())
+            .await?
+            .expect("digests differ");
+        assert_eq!(comparison.unknown, vec![unknown]);
+        assert_eq!(comparison.overlap, 0.5);
+        assert!(!comparison.is_partition());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn mostly_unknown_members_suggest_a_partition() -> anyhow::Result<()> {
+        let infohash = [5u8; 20];
+        let (a, b) = (
+            PartitionHealing::new(infohash),
+            PartitionHealing::new(infohash),
+        );
+        let (endpoint_a, _router_a) = node(a.clone()).await?;
+        let (endpoint_b, _router_b) = node(b.clone()).await?;
+        learn(&endpoint_a, &endpoint_b)?;
+
+        let shared = SecretKey::generate().public();
+        let others: Vec<EndpointId> = (0..3).map(|_| SecretKey::generate().public()).collect();
+        a.observe(endpoint_a.id(), [endpoint_b.id(), shared], WINDOW);
+        b.observe(
+            endpoint_b.id(),
+            others.iter().copied().chain([endpoint_a.id(), shared]),
+            WINDOW,
+        );
+        let comparison = a
+            .exchange(&endpoint_a, endpoint_b.id())
+            .await?
+            .expect("digests differ");
+        assert_eq!(comparison.overlap, 0.25);
+        assert_eq!(comparison.unknown.len(), 3);
+        assert!(comparison.is_partition());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn gated_members_are_only_listed_to_admitted_peers() -> anyhow::Result<()> {
+        let infohash = [4u8; 20];
+        let a = PartitionHealing::new(infohash);
+        let b = PartitionHealing::new(infohash).with_membership(Membership::open(infohash));
+        let (endpoint_a, _router_a) = node(a.clone()).await?;
+        let (endpoint_b, _router_b) = node(b.clone()).await?;
+        learn(&endpoint_a, &endpoint_b)?;
+
+        b.observe(endpoint_b.id(), [SecretKey::generate().public()], WINDOW);
+        assert!(a.exchange(&endpoint_a, endpoint_b.id()).await.is_err());
+        Ok(())
+    }
+}
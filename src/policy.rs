@@ -0,0 +1,179 @@
+use std::time::Duration;
+
+/// Timeouts, retry delays and poll intervals used by the discovery tasks.
+///
+/// The defaults match what the tracker always used; mobile deployments usually
+/// want longer timeouts and more jitter, servers shorter ones.
+#[derive(Debug, Clone)]
+pub struct Timings {
+    /// Max wait for the DHT to bootstrap (default: 15s)
+    pub dht_bootstrap_timeout: Duration,
+    /// Delay between DHT initialization attempts (default: 2s)
+    pub dht_init_retry: Duration,
+    /// Timeout for a single DHT announce (default: 30s)
+    pub announce_timeout: Duration,
+    /// First retry delay after a failed announce (default: 5s)
+    pub announce_backoff_min: Duration,
+    /// Retry delay cap, doubled from `announce_backoff_min` on every failure (default: 60s)
+    pub announce_backoff_max: Duration,
+    /// Timeout for collecting peers from one DHT lookup (default: 30s)
+    pub lookup_timeout: Duration,
+    /// Timeout for resolving a peer's addressing record from the DHT (default: 10s)
+    pub addr_resolve_timeout: Duration,
+    /// How often a connector checks for an active path to the peer (default: 250ms)
+    pub connector_poll_interval: Duration,
+    /// How long the dispatcher collects candidates arriving together before dialing
+    /// them by priority (default: 250ms)
+    pub dispatch_batch_window: Duration,
+    /// How often idle dispatch and source tasks wake up to check whether discovery stopped (default: 1s)
+    pub dispatch_idle_poll: Duration,
+    /// Added to the announce interval to get the default max age of a peer record (default: 10s)
+    pub staleness_buffer: Duration,
+    /// Randomizes every sleep by up to +-`jitter` of its length, clamped to 0.0..=1.0,
+    /// NaN disables it (default: 0.0)
+    pub jitter: f64,
+}
+
+impl Default for Timings {
+    fn default() -> Self {
+        Self {
+            dht_bootstrap_timeout: Duration::from_secs(15),
+            dht_init_retry: Duration::from_secs(2),
+            announce_timeout: Duration::from_secs(30),
+            announce_backoff_min: Duration::from_secs(5),
+            announce_backoff_max: Duration::from_secs(60),
+            lookup_timeout: Duration::from_secs(30),
+            addr_resolve_timeout: Duration::from_secs(10),
+            connector_poll_interval: Duration::from_millis(250),
+            dispatch_batch_window: Duration::from_millis(250),
+            dispatch_idle_poll: Duration::from_secs(1),
+            staleness_buffer: Duration::from_secs(10),
+            jitter: 0.0,
+        }
+    }
+}
+
+impl Timings {
+    /// `duration` randomized by the configured jitter.
+    pub fn jittered(&self, duration: Duration) -> Duration {
+        if self.jitter.is_nan() {
+            return duration;
+        }
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return duration;
+        }
+        // factor in [1 - jitter, 1 + jitter)
+        let factor = 1.0 + jitter * (2.0 * rand::random::<f64>() - 1.0);
+        duration.mul_f64(factor)
+    }
+
+    /// Next announce backoff after a failure.
+    pub(crate) fn next_announce_backoff(&self, backoff: Duration) -> Duration {
+        (backoff * 2).min(self.announce_backoff_max)
+    }
+}
+
+/// When the announce task writes our record to the DHT.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AnnounceMode {
+    /// Announce every announce interval (default)
+    #[default]
+    Always,
+    /// Announce with probability `target_records / visible records`, always while
+    /// no more than `target_records` fresh records are visible in our shard.
+    /// Cuts DHT writes in big swarms while newcomers still find enough peers.
+    Adaptive { target_records: usize },
+}
+
+impl AnnounceMode {
+    /// Probability to announce this round, `visible_records` is None before the first lookup.
+    pub fn announce_probability(&self, visible_records: Option<usize>) -> f64 {
+        match (self, visible_records) {
+            (AnnounceMode::Always, _) | (_, None) => 1.0,
+            (AnnounceMode::Adaptive { target_records }, Some(visible)) => {
+                let target = (*target_records).max(1);
+                if visible <= target {
+                    1.0
+                } else {
+                    target as f64 / visible as f64
+                }
+            }
+        }
+    }
+}
+
+/// What to do with peer records when the local clock can't be read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ClockErrorBehavior {
+    /// Accept every record, age checks are skipped (default)
+    #[default]
+    AcceptAll,
+    /// Reject every record until the clock is readable again
+    RejectAll,
+}
+
+/// Classification of a signed peer record timestamp.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordFreshness {
+    Fresh,
+    /// Older than the max age
+    Stale,
+    /// Further in the future than the allowed clock skew
+    FromFuture,
+    /// Local clock is before the unix epoch
+    ClockError,
+}
+
+/// Which signed peer records are recent enough to dial.
+#[derive(Debug, Clone)]
+pub struct StalenessPolicy {
+    /// Max record age, None uses our announce interval + `Timings::staleness_buffer` (default: None)
+    ///
+    /// Set this when peers on the topic run with different announce intervals.
+    pub max_age: Option<Duration>,
+    /// How far a record timestamp may be ahead of our clock (default: 30s)
+    pub max_future_skew: Duration,
+    pub on_clock_error: ClockErrorBehavior,
+}
+
+impl Default for StalenessPolicy {
+    fn default() -> Self {
+        Self {
+            max_age: None,
+            max_future_skew: Duration::from_secs(30),
+            on_clock_error: ClockErrorBehavior::AcceptAll,
+        }
+    }
+}
+
+impl StalenessPolicy {
+    /// Classify a record timestamp (microseconds since the unix epoch) against the local clock.
+    pub fn classify(&self, timestamp: u64, default_max_age: Duration) -> RecordFreshness {
+        let Ok(now) = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) else {
+            return RecordFreshness::ClockError;
+        };
+        let now = now.as_micros();
+        let timestamp = timestamp as u128;
+
+        if timestamp > now {
+            if timestamp - now > self.max_future_skew.as_micros() {
+                return RecordFreshness::FromFuture;
+            }
+            return RecordFreshness::Fresh;
+        }
+        if now - timestamp > self.max_age.unwrap_or(default_max_age).as_micros() {
+            RecordFreshness::Stale
+        } else {
+            RecordFreshness::Fresh
+        }
+    }
+
+    pub fn accepts(&self, freshness: RecordFreshness) -> bool {
+        match freshness {
+            RecordFreshness::Fresh => true,
+            RecordFreshness::Stale | RecordFreshness::FromFuture => false,
+            RecordFreshness::ClockError => self.on_clock_error == ClockErrorBehavior::AcceptAll,
+        }
+    }
+}
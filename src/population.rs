@@ -0,0 +1,199 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::topic::shard_for_key;
+
+/// Rough number of active members of a topic, see `TopicDiscoveryHandle::population`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct PopulationEstimate {
+    /// Estimated active members including us, extrapolated to unsampled shards
+    pub estimate: usize,
+    /// Distinct peers with a fresh record seen within the record max age
+    pub observed: usize,
+    pub confidence: EstimateConfidence,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub enum EstimateConfidence {
+    /// No lookup finished yet
+    #[default]
+    None,
+    /// Few lookups or few records, the estimate may be far off
+    Low,
+    Medium,
+    /// Several lookups covering every shard
+    High,
+}
+
+/// Distinct fresh records across lookups and shards, expired after the record max age.
+#[derive(Debug)]
+pub(crate) struct PopulationEstimator {
+    shards: u16,
+    window: Duration,
+    /// Last time each peer's fresh record was seen
+    peers: HashMap<[u8; 32], Instant>,
+    /// Last lookup of each shard
+    sampled_shards: HashMap<u16, Instant>,
+    lookups: Vec<Instant>,
+    /// Peers of the last lookup without a record seen within the window
+    new_peers: usize,
+}
+
+impl PopulationEstimator {
+    pub fn new(shards: u16, window: Duration) -> Self {
+        Self {
+            shards: shards.max(1),
+            window,
+            peers: HashMap::new(),
+            sampled_shards: HashMap::new(),
+            lookups: Vec::new(),
+            new_peers: 0,
+        }
+    }
+
+    /// Add the fresh keys of a lookup that sampled `shards`.
+    pub fn lookup_finished(
+        &mut self,
+        shards: &[u16],
+        fresh_keys: &HashSet<[u8; 32]>,
+    ) -> PopulationEstimate {
+        let now = Instant::now();
+        for shard in shards {
+            self.sampled_shards.insert(*shard, now);
+        }
+        self.expire(now);
+        self.new_peers = 0;
+        for key in fresh_keys {
+            // records outside the sampled shards come from nodes using another shard count
+            if shards.contains(&shard_for_key(key, self.shards))
+                && self.peers.insert(*key, now).is_none()
+            {
+                self.new_peers += 1;
+            }
+        }
+        self.lookups.push(now);
+        self.estimate()
+    }
+
+    /// Peers of the last lookup that had no record within the window.
+    pub fn new_peers(&self) -> usize {
+        self.new_peers
+    }
+
+    fn expire(&mut self, now: Instant) {
+        let window = self.window;
+        self.peers
+            .retain(|_, last_seen| now.duration_since(*last_seen) <= window);
+        self.sampled_shards
+            .retain(|_, last_sampled| now.duration_since(*last_sampled) <= window);
+        self.lookups.retain(|at| now.duration_since(*at) <= window);
+    }
+
+    fn estimate(&self) -> PopulationEstimate {
+        let observed = self.peers.len();
+        let covered = self.sampled_shards.len().max(1);
+        let extrapolated = (observed * self.shards as usize).div_ceil(covered);
+        let confidence = if self.lookups.is_empty() {
+            EstimateConfidence::None
+        } else if self.lookups.len() < 2 || (observed > 0 && observed < 3) {
+            EstimateConfidence::Low
+        } else if self.lookups.len() >= 3 && covered == self.shards as usize {
+            EstimateConfidence::High
+        } else {
+            EstimateConfidence::Medium
+        };
+        PopulationEstimate {
+            estimate: extrapolated + 1,
+            observed,
+            confidence,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(600);
+
+    /// `count` distinct keys that land in `shard` of `shards`.
+    fn keys_in_shard(shard: u16, shards: u16, count: usize) -> HashSet<[u8; 32]> {
+        (0..=u16::MAX)
+            .map(|i| {
+                let mut key = [0u8; 32];
+                key[..2].copy_from_slice(&i.to_be_bytes());
+                key
+            })
+            .filter(|key| shard_for_key(key, shards) == shard)
+            .take(count)
+            .collect()
+    }
+
+    #[test]
+    fn estimate_extrapolates_sampled_shards_and_counts_us() {
+        let mut estimator = PopulationEstimator::new(4, WINDOW);
+        let mut keys = keys_in_shard(0, 4, 5);
+        // from a node with another shard count, not part of the sampled shard
+        keys.extend(keys_in_shard(1, 4, 2));
+
+        let estimate = estimator.lookup_finished(&[0], &keys);
+        assert_eq!(estimate.observed, 5);
+        assert_eq!(estimate.estimate, 5 * 4 + 1);
+
+        let estimate = estimator.lookup_finished(&[1], &keys_in_shard(1, 4, 3));
+        assert_eq!(estimate.observed, 8);
+        assert_eq!(estimate.estimate, (8 * 4usize).div_ceil(2) + 1);
+    }
+
+    #[test]
+    fn confidence_grows_with_lookups_records_and_coverage() {
+        let mut estimator = PopulationEstimator::new(2, WINDOW);
+        assert_eq!(estimator.estimate().confidence, EstimateConfidence::None);
+
+        let many = keys_in_shard(0, 2, 5);
+        let first = estimator.lookup_finished(&[0], &many);
+        assert_eq!(first.confidence, EstimateConfidence::Low);
+        // two lookups but only one shard covered
+        let second = estimator.lookup_finished(&[0], &many);
+        assert_eq!(second.confidence, EstimateConfidence::Medium);
+        let third = estimator.lookup_finished(&[1], &keys_in_shard(1, 2, 5));
+        assert_eq!(third.confidence, EstimateConfidence::High);
+
+        // a couple of records say little however often we look
+        let mut sparse = PopulationEstimator::new(1, WINDOW);
+        let few = keys_in_shard(0, 1, 2);
+        for _ in 0..3 {
+            assert_eq!(
+                sparse.lookup_finished(&[0], &few).confidence,
+                EstimateConfidence::Low
+            );
+        }
+        // an empty topic after several lookups is a confident answer
+        let mut empty = PopulationEstimator::new(1, WINDOW);
+        for _ in 0..3 {
+            empty.lookup_finished(&[0], &HashSet::new());
+        }
+        assert_eq!(
+            empty.estimate(),
+            PopulationEstimate {
+                estimate: 1,
+                observed: 0,
+                confidence: EstimateConfidence::High,
+            }
+        );
+    }
+
+    #[test]
+    fn records_and_lookups_expire_after_the_window() {
+        let mut estimator = PopulationEstimator::new(1, Duration::ZERO);
+        estimator.lookup_finished(&[0], &keys_in_shard(0, 1, 5));
+        std::thread::sleep(Duration::from_millis(5));
+        let estimate = estimator.lookup_finished(&[0], &HashSet::new());
+        assert_eq!(estimate.observed, 0);
+        assert_eq!(estimate.confidence, EstimateConfidence::Low);
+    }
+}
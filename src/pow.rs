@@ -0,0 +1,103 @@
+use std::time::SystemTime;
+
+use dht::async_dht::AsyncDht;
+use ed25519_dalek::SigningKey;
+use iroh::EndpointId;
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+
+/// Domain separator of proof-of-work hashes.
+const POW_DOMAIN: &[u8] = b"/iroh/topic-discovery/pow/v1";
+
+/// Domain separator of the proof record salt.
+const POW_RECORD_DOMAIN: &[u8] = b"/iroh/topic-discovery/pow-record/v1";
+
+/// Nonce whose hash together with an `EndpointId` and topic infohash has a
+/// number of leading zero bits, making mass-generated identities expensive.
+///
+/// Each additional bit of difficulty doubles the expected work.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProofOfWork {
+    pub nonce: u64,
+}
+
+impl ProofOfWork {
+    /// Search a nonce for `difficulty` leading zero bits. This blocks, run it in
+    /// `spawn_blocking` for high difficulties and store the result.
+    pub fn solve(infohash: &[u8; 20], endpoint: &EndpointId, difficulty: u8) -> Self {
+        let mut nonce = rand::random::<u64>();
+        loop {
+            let proof = Self { nonce };
+            if proof.verify(infohash, endpoint, difficulty) {
+                return proof;
+            }
+            nonce = nonce.wrapping_add(1);
+        }
+    }
+
+    /// Leading zero bits this proof achieves for `endpoint` and `infohash`.
+    pub fn difficulty(&self, infohash: &[u8; 20], endpoint: &EndpointId) -> u32 {
+        let mut hasher = sha2::Sha512::new();
+        hasher.update(POW_DOMAIN);
+        hasher.update(infohash);
+        hasher.update(endpoint.as_bytes());
+        hasher.update(self.nonce.to_be_bytes());
+        leading_zero_bits(&hasher.finalize()[..32])
+    }
+
+    pub fn verify(&self, infohash: &[u8; 20], endpoint: &EndpointId, difficulty: u8) -> bool {
+        self.difficulty(infohash, endpoint) >= u32::from(difficulty)
+    }
+}
+
+/// BEP44 salt of the proof record for the topic with `infohash`.
+fn proof_salt(infohash: &[u8; 20]) -> [u8; 32] {
+    let mut hasher = sha2::Sha512::new();
+    hasher.update(POW_RECORD_DOMAIN);
+    hasher.update(infohash);
+    hasher.finalize()[..32].try_into().expect("hashing failed")
+}
+
+/// Publish our proof for the topic with `infohash` as a BEP44 mutable item next to
+/// our announcement, so peers can check it before they pick us.
+pub(crate) async fn publish_proof(
+    dht: &AsyncDht,
+    signing_key: &SigningKey,
+    infohash: &[u8; 20],
+    proof: &ProofOfWork,
+) -> anyhow::Result<()> {
+    let value = postcard::to_stdvec(proof)?;
+    // refreshed every announce, newer items win
+    let seq = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as i64;
+    let salt = proof_salt(infohash);
+    let item = dht::MutableItem::new(signing_key, &value, seq, Some(&salt));
+    dht.put_mutable(item, None).await?;
+    Ok(())
+}
+
+/// Proof `peer` published for the topic with `infohash`, None if there is none.
+pub(crate) async fn fetch_proof(
+    dht: &AsyncDht,
+    peer: EndpointId,
+    infohash: &[u8; 20],
+) -> Option<ProofOfWork> {
+    let salt = proof_salt(infohash);
+    let item = dht
+        .get_mutable_most_recent(peer.as_bytes(), Some(&salt))
+        .await?;
+    postcard::from_bytes(item.value()).ok()
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
@@ -0,0 +1,109 @@
+use std::{collections::HashSet, time::Duration};
+
+use iroh::EndpointId;
+
+/// How the discovery task picks the pause between lookups once connected.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DiscoverySchedule {
+    /// `discovery_interval`, or `discovery_interval_first_connected` right after
+    /// the first neighbor connected (default)
+    #[default]
+    Fixed,
+    /// Halve the interval when a round brings peers the population estimate hasn't
+    /// seen within the record max age or neighbor churn, grow it by half when
+    /// nothing changed, bounded by `discovery_interval_first_connected` and
+    /// `discovery_interval`
+    Adaptive,
+}
+
+/// State of `DiscoverySchedule::Adaptive` across rounds.
+#[derive(Debug)]
+pub(crate) struct AdaptiveScheduler {
+    interval: Duration,
+    previous_neighbors: HashSet<EndpointId>,
+}
+
+impl AdaptiveScheduler {
+    pub fn new(initial: Duration) -> Self {
+        Self {
+            interval: initial,
+            previous_neighbors: HashSet::new(),
+        }
+    }
+
+    /// Interval until the next round, from the peers new to the population estimate
+    /// and the live neighbors.
+    pub fn next_interval(
+        &mut self,
+        novelty: usize,
+        live_neighbors: HashSet<EndpointId>,
+        min: Duration,
+        max: Duration,
+    ) -> Duration {
+        let churn = live_neighbors
+            .symmetric_difference(&self.previous_neighbors)
+            .count();
+        self.previous_neighbors = live_neighbors;
+
+        let interval = if novelty + churn > 0 {
+            self.interval / 2
+        } else {
+            self.interval.mul_f64(1.5)
+        };
+        self.interval = interval.clamp(min, max.max(min));
+        tracing::debug!(
+            "adaptive_scheduler: {novelty} new records, {churn} neighbor changes, next interval {:?}",
+            self.interval
+        );
+        self.interval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::population::PopulationEstimator;
+
+    const MIN: Duration = Duration::from_secs(5);
+    const MAX: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn records_seen_within_the_window_are_not_novel() {
+        let mut estimator = PopulationEstimator::new(1, Duration::from_secs(600));
+        let mut scheduler = AdaptiveScheduler::new(Duration::from_secs(20));
+        let (a, b) = ([1u8; 32], [2u8; 32]);
+
+        estimator.lookup_finished(&[0], &HashSet::from([a]));
+        assert_eq!(estimator.new_peers(), 1);
+        let first = scheduler.next_interval(estimator.new_peers(), HashSet::new(), MIN, MAX);
+        assert_eq!(first, Duration::from_secs(10));
+
+        estimator.lookup_finished(&[0], &HashSet::from([b]));
+        assert_eq!(estimator.new_peers(), 1);
+        scheduler.next_interval(estimator.new_peers(), HashSet::new(), MIN, MAX);
+
+        // a dropped out of one lookup and came back, it's still the same population
+        estimator.lookup_finished(&[0], &HashSet::from([a]));
+        assert_eq!(estimator.new_peers(), 0);
+        let quiet = scheduler.next_interval(estimator.new_peers(), HashSet::new(), MIN, MAX);
+        assert_eq!(quiet, MIN.mul_f64(1.5));
+    }
+
+    #[test]
+    fn neighbor_churn_shortens_and_quiet_rounds_grow_within_bounds() {
+        let mut scheduler = AdaptiveScheduler::new(Duration::from_secs(40));
+        let neighbor = iroh::SecretKey::generate().public();
+
+        let churned = scheduler.next_interval(0, HashSet::from([neighbor]), MIN, MAX);
+        assert_eq!(churned, Duration::from_secs(20));
+        let mut interval = churned;
+        for _ in 0..10 {
+            interval = scheduler.next_interval(0, HashSet::from([neighbor]), MIN, MAX);
+        }
+        assert_eq!(interval, MAX);
+        for _ in 0..10 {
+            interval = scheduler.next_interval(1, HashSet::from([neighbor]), MIN, MAX);
+        }
+        assert_eq!(interval, MIN);
+    }
+}
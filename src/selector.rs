@@ -0,0 +1,147 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use iroh::EndpointId;
+
+/// A fresh peer record from a discovery lookup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerCandidate {
+    pub peer: EndpointId,
+    /// Announcement timestamp in microseconds since the unix epoch
+    pub timestamp: u64,
+}
+
+/// Decides which discovered peers get dialed.
+///
+/// `select` is called for every batch of fresh, not recently attempted candidates
+/// as it arrives from the DHT, with the remaining dial budget of the round.
+pub trait PeerSelector: std::fmt::Debug + Send + Sync {
+    /// Returns up to `budget` candidates in the order they should be dialed.
+    fn select(&self, candidates: Vec<PeerCandidate>, budget: usize) -> Vec<PeerCandidate>;
+
+    /// Called when a dial to a selected peer finished.
+    fn record_outcome(&self, _peer: &EndpointId, _connected: bool) {}
+}
+
+/// Built-in selection strategies.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PeerSelection {
+    /// Most recent announcements first
+    #[default]
+    Newest,
+    /// Uniform random choice
+    Random,
+    /// Random, weighted by the peer's past connection success rate
+    WeightedBySuccess,
+    /// Peers we selected least often (never selected first)
+    RoundRobinUnseen,
+}
+
+impl PeerSelection {
+    pub fn into_selector(self) -> Arc<dyn PeerSelector> {
+        match self {
+            PeerSelection::Newest => Arc::new(NewestSelector),
+            PeerSelection::Random => Arc::new(RandomSelector),
+            PeerSelection::WeightedBySuccess => Arc::new(WeightedSuccessSelector::default()),
+            PeerSelection::RoundRobinUnseen => Arc::new(RoundRobinSelector::default()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NewestSelector;
+
+impl PeerSelector for NewestSelector {
+    fn select(&self, mut candidates: Vec<PeerCandidate>, budget: usize) -> Vec<PeerCandidate> {
+        candidates.sort_by_key(|candidate| std::cmp::Reverse(candidate.timestamp));
+        candidates.truncate(budget);
+        candidates
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RandomSelector;
+
+impl PeerSelector for RandomSelector {
+    fn select(&self, mut candidates: Vec<PeerCandidate>, budget: usize) -> Vec<PeerCandidate> {
+        candidates.sort_by_cached_key(|_| rand::random::<u64>());
+        candidates.truncate(budget);
+        candidates
+    }
+}
+
+/// Weighted random sampling without replacement (Efraimidis-Spirakis), weight is
+/// the Laplace smoothed success rate so unknown peers start at 0.5.
+#[derive(Debug, Default)]
+pub struct WeightedSuccessSelector {
+    /// (successes, failures) per peer
+    outcomes: Mutex<HashMap<EndpointId, (u32, u32)>>,
+}
+
+impl WeightedSuccessSelector {
+    fn weight(&self, peer: &EndpointId) -> f64 {
+        let outcomes = self.outcomes.lock().expect("poisoned");
+        let (successes, failures) = outcomes.get(peer).copied().unwrap_or_default();
+        (successes as f64 + 1.0) / (successes as f64 + failures as f64 + 2.0)
+    }
+}
+
+impl PeerSelector for WeightedSuccessSelector {
+    fn select(&self, candidates: Vec<PeerCandidate>, budget: usize) -> Vec<PeerCandidate> {
+        let mut keyed = candidates
+            .into_iter()
+            .map(|candidate| {
+                let key = rand::random::<f64>().powf(1.0 / self.weight(&candidate.peer));
+                (key, candidate)
+            })
+            .collect::<Vec<_>>();
+        keyed.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        keyed
+            .into_iter()
+            .take(budget)
+            .map(|(_, candidate)| candidate)
+            .collect()
+    }
+
+    fn record_outcome(&self, peer: &EndpointId, connected: bool) {
+        let mut outcomes = self.outcomes.lock().expect("poisoned");
+        let entry = outcomes.entry(*peer).or_default();
+        if connected {
+            entry.0 = entry.0.saturating_add(1);
+        } else {
+            entry.1 = entry.1.saturating_add(1);
+        }
+    }
+}
+
+/// Spreads dials over all announcers instead of the same few: peers we never
+/// selected come first, then the ones selected longest ago.
+#[derive(Debug, Default)]
+pub struct RoundRobinSelector {
+    /// Selection counter value when the peer was last selected
+    last_selected: Mutex<HashMap<EndpointId, u64>>,
+    counter: Mutex<u64>,
+}
+
+impl PeerSelector for RoundRobinSelector {
+    fn select(&self, mut candidates: Vec<PeerCandidate>, budget: usize) -> Vec<PeerCandidate> {
+        let mut last_selected = self.last_selected.lock().expect("poisoned");
+        let mut counter = self.counter.lock().expect("poisoned");
+
+        // unseen (0) first, ties broken by newest announcement
+        candidates.sort_by_key(|candidate| {
+            (
+                last_selected.get(&candidate.peer).copied().unwrap_or(0),
+                std::cmp::Reverse(candidate.timestamp),
+            )
+        });
+        candidates.truncate(budget);
+        for candidate in &candidates {
+            *counter += 1;
+            last_selected.insert(candidate.peer, *counter);
+        }
+        candidates
+    }
+}
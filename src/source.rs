@@ -0,0 +1,328 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use futures_lite::{StreamExt, stream::Boxed};
+use iroh::{EndpointAddr, EndpointId, RelayUrl, TransportAddr};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+/// Where a candidate peer came from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub enum SourceKind {
+    /// Signed peer records from the mainline DHT
+    Dht,
+    /// Multicast announcements on the local network
+    Lan,
+    /// Static bootstrap peers
+    Bootstrap,
+    /// Peers we connected to in earlier sessions
+    Cache,
+    /// Peers learned from other members of the topic
+    PeerExchange,
+    Custom(String),
+}
+
+impl SourceKind {
+    /// Priority and rate limit used when none is configured for this kind.
+    pub fn default_policy(&self) -> SourcePolicy {
+        let priority = match self {
+            SourceKind::Lan => 200,
+            SourceKind::Bootstrap => 150,
+            SourceKind::Cache => 100,
+            SourceKind::PeerExchange => 80,
+            SourceKind::Dht | SourceKind::Custom(_) => 50,
+        };
+        SourcePolicy {
+            priority,
+            max_dials_per_minute: None,
+        }
+    }
+}
+
+/// How candidates of one source kind are dialed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourcePolicy {
+    /// Candidates arriving together are dialed highest priority first
+    pub priority: u8,
+    /// Dials per minute, excess candidates wait for the next minute (default: unlimited)
+    pub max_dials_per_minute: Option<u32>,
+}
+
+/// A peer reported by a discovery source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredPeer {
+    pub peer: EndpointId,
+    /// Direct addresses to try, may be empty if iroh can look the peer up itself
+    pub direct_addrs: Vec<SocketAddr>,
+    pub relay_url: Option<RelayUrl>,
+}
+
+impl DiscoveredPeer {
+    pub fn new(peer: EndpointId) -> Self {
+        Self {
+            peer,
+            direct_addrs: Vec::new(),
+            relay_url: None,
+        }
+    }
+
+    pub fn with_direct_addrs(mut self, direct_addrs: Vec<SocketAddr>) -> Self {
+        self.direct_addrs = direct_addrs;
+        self
+    }
+
+    pub fn with_relay_url(mut self, relay_url: Option<RelayUrl>) -> Self {
+        self.relay_url = relay_url;
+        self
+    }
+}
+
+impl From<EndpointAddr> for DiscoveredPeer {
+    fn from(addr: EndpointAddr) -> Self {
+        Self {
+            peer: addr.id,
+            direct_addrs: addr.ip_addrs().copied().collect(),
+            relay_url: addr.relay_urls().next().cloned(),
+        }
+    }
+}
+
+impl From<&DiscoveredPeer> for EndpointAddr {
+    fn from(found: &DiscoveredPeer) -> Self {
+        EndpointAddr::from_parts(
+            found.peer,
+            found
+                .relay_url
+                .iter()
+                .cloned()
+                .map(TransportAddr::Relay)
+                .chain(found.direct_addrs.iter().copied().map(TransportAddr::Ip)),
+        )
+    }
+}
+
+/// Additional source of candidate peers, combined with the DHT and LAN discovery.
+///
+/// Candidates of all sources are dialed by one dispatcher that applies the
+/// `SourcePolicy` of their kind.
+pub trait DiscoverySource: std::fmt::Debug + Send + Sync {
+    fn kind(&self) -> SourceKind;
+
+    /// Candidates for the topic with the given DHT infohashes, polled until discovery stops.
+    /// Called again once the stream finished, whenever the DHT turns out to be degraded.
+    fn peers(&self, infohashes: &[[u8; 20]]) -> Boxed<DiscoveredPeer>;
+
+    /// Called for every peer that connected, whichever source found it.
+    fn peer_connected(&self, _peer: &DiscoveredPeer) {}
+}
+
+/// Fixed list of peers, reported once when discovery starts.
+#[derive(Debug, Clone)]
+pub struct StaticSource {
+    kind: SourceKind,
+    peers: Vec<DiscoveredPeer>,
+}
+
+impl StaticSource {
+    pub fn new(kind: SourceKind, peers: impl IntoIterator<Item = DiscoveredPeer>) -> Self {
+        Self {
+            kind,
+            peers: peers.into_iter().collect(),
+        }
+    }
+}
+
+impl DiscoverySource for StaticSource {
+    fn kind(&self) -> SourceKind {
+        self.kind.clone()
+    }
+
+    fn peers(&self, _infohashes: &[[u8; 20]]) -> Boxed<DiscoveredPeer> {
+        futures_lite::stream::iter(self.peers.clone()).boxed()
+    }
+}
+
+/// Peers pushed by the application, e.g. from a peer exchange protocol.
+#[derive(Debug)]
+pub struct ChannelSource {
+    kind: SourceKind,
+    receiver: Mutex<Option<mpsc::Receiver<DiscoveredPeer>>>,
+}
+
+impl ChannelSource {
+    /// The source can be attached to one subscription, later `peers` calls yield nothing.
+    pub fn new(kind: SourceKind, capacity: usize) -> (mpsc::Sender<DiscoveredPeer>, Self) {
+        let (sender, receiver) = mpsc::channel(capacity);
+        let source = Self {
+            kind,
+            receiver: Mutex::new(Some(receiver)),
+        };
+        (sender, source)
+    }
+}
+
+impl DiscoverySource for ChannelSource {
+    fn kind(&self) -> SourceKind {
+        self.kind.clone()
+    }
+
+    fn peers(&self, _infohashes: &[[u8; 20]]) -> Boxed<DiscoveredPeer> {
+        let Some(receiver) = self.receiver.lock().expect("poisoned").take() else {
+            tracing::debug!("channel_source: receiver already taken");
+            return futures_lite::stream::empty().boxed();
+        };
+        futures_lite::stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|found| (found, receiver))
+        })
+        .boxed()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedPeer {
+    endpoint_id: [u8; 32],
+    direct_addrs: Vec<SocketAddr>,
+    last_connected_unix_ms: u64,
+}
+
+/// Peers we connected to before, persisted as JSON so restarts skip the DHT lookup.
+#[derive(Debug)]
+pub struct PeerCache {
+    path: PathBuf,
+    max_entries: usize,
+    entries: Arc<Mutex<Vec<CachedPeer>>>,
+    /// Serializes writes of the file, held on a blocking thread
+    write_lock: Arc<Mutex<()>>,
+}
+
+impl PeerCache {
+    /// Loads `path` if it exists. Keeps the `max_entries` most recently connected peers.
+    pub fn open(path: impl Into<PathBuf>, max_entries: usize) -> anyhow::Result<Self> {
+        let path = path.into();
+        let entries = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            path,
+            max_entries,
+            entries: Arc::new(Mutex::new(entries)),
+            write_lock: Arc::new(Mutex::new(())),
+        })
+    }
+
+    /// Cached peers, most recently connected first.
+    pub fn peers(&self) -> Vec<DiscoveredPeer> {
+        self.entries
+            .lock()
+            .expect("poisoned")
+            .iter()
+            .filter_map(|cached| {
+                let peer = EndpointId::from_bytes(&cached.endpoint_id).ok()?;
+                Some(DiscoveredPeer::new(peer).with_direct_addrs(cached.direct_addrs.clone()))
+            })
+            .collect()
+    }
+
+    /// Writes the current entries on a blocking thread, or right away outside a runtime.
+    fn save_in_background(&self) {
+        let path = self.path.clone();
+        let entries = self.entries.clone();
+        let write_lock = self.write_lock.clone();
+        let save = move || {
+            let _guard = write_lock.lock().expect("poisoned");
+            // the latest entries, a later save may have been queued behind us
+            let snapshot = entries.lock().expect("poisoned").clone();
+            if let Err(e) = save(&path, &snapshot) {
+                tracing::warn!("peer_cache: failed to save {}: {e}", path.display());
+            }
+        };
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => drop(runtime.spawn_blocking(save)),
+            Err(_) => save(),
+        }
+    }
+}
+
+fn save(path: &Path, entries: &[CachedPeer]) -> anyhow::Result<()> {
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, serde_json::to_vec(entries)?)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+impl DiscoverySource for PeerCache {
+    fn kind(&self) -> SourceKind {
+        SourceKind::Cache
+    }
+
+    fn peers(&self, _infohashes: &[[u8; 20]]) -> Boxed<DiscoveredPeer> {
+        futures_lite::stream::iter(PeerCache::peers(self)).boxed()
+    }
+
+    fn peer_connected(&self, peer: &DiscoveredPeer) {
+        let mut entries = self.entries.lock().expect("poisoned");
+        let previous = entries
+            .iter()
+            .position(|cached| cached.endpoint_id == *peer.peer.as_bytes())
+            .map(|index| entries.remove(index));
+        // keep known addresses if the source that found the peer didn't have any
+        let direct_addrs = match previous {
+            Some(previous) if peer.direct_addrs.is_empty() => previous.direct_addrs,
+            _ => peer.direct_addrs.clone(),
+        };
+        entries.insert(
+            0,
+            CachedPeer {
+                endpoint_id: *peer.peer.as_bytes(),
+                direct_addrs,
+                last_connected_unix_ms: crate::diagnostics::unix_millis(SystemTime::now()),
+            },
+        );
+        entries.truncate(self.max_entries);
+        drop(entries);
+        // called from the connector tasks, keep file io off the runtime
+        self.save_in_background();
+    }
+}
+
+/// Per source kind dial counters over fixed one minute windows.
+#[derive(Debug, Default)]
+pub(crate) struct DialLimiter {
+    windows: HashMap<SourceKind, (Instant, u32)>,
+}
+
+impl DialLimiter {
+    /// Counts a dial and returns true if `policy` allows it.
+    pub fn try_acquire(&mut self, kind: &SourceKind, policy: &SourcePolicy) -> bool {
+        let Some(max) = policy.max_dials_per_minute else {
+            return true;
+        };
+        let now = Instant::now();
+        let (window_start, dials) = self.windows.entry(kind.clone()).or_insert((now, 0));
+        if now.duration_since(*window_start) >= Duration::from_secs(60) {
+            *window_start = now;
+            *dials = 0;
+        }
+        if *dials >= max {
+            return false;
+        }
+        *dials += 1;
+        true
+    }
+
+    /// When `kind` may dial again after `try_acquire` refused it.
+    pub fn available_at(&self, kind: &SourceKind) -> Instant {
+        self.windows
+            .get(kind)
+            .map_or_else(Instant::now, |(window_start, _)| {
+                *window_start + Duration::from_secs(60)
+            })
+    }
+}
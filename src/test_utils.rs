@@ -0,0 +1,50 @@
+//! Setup shared by the unit tests.
+
+use dht::async_dht::AsyncDht;
+use iroh::{
+    Endpoint,
+    address_lookup::MemoryLookup,
+    protocol::{Router, RouterBuilder},
+};
+
+/// Local DHT of 5 nodes and a client bootstrapped from it, keep the testnet alive
+/// while the client is used.
+pub(crate) async fn testnet_dht() -> anyhow::Result<(dht::Testnet, AsyncDht)> {
+    let testnet = dht::Testnet::new_async(5).await?;
+    let dht = dht::Dht::builder()
+        .bootstrap(&testnet.bootstrap)
+        .build_async()
+        .await?;
+    Ok((testnet, dht))
+}
+
+/// Endpoint without relays or address lookup, see `learn`.
+pub(crate) async fn endpoint() -> anyhow::Result<Endpoint> {
+    Ok(Endpoint::builder(iroh::endpoint::presets::Minimal)
+        .bind()
+        .await?)
+}
+
+/// New endpoint serving the protocols `accept` adds to its router.
+pub(crate) async fn node(
+    accept: impl FnOnce(RouterBuilder) -> RouterBuilder,
+) -> anyhow::Result<(Endpoint, Router)> {
+    let endpoint = endpoint().await?;
+    let router = serve(endpoint.clone(), accept);
+    Ok((endpoint, router))
+}
+
+pub(crate) fn serve(
+    endpoint: Endpoint,
+    accept: impl FnOnce(RouterBuilder) -> RouterBuilder,
+) -> Router {
+    accept(Router::builder(endpoint)).spawn()
+}
+
+/// Let `from` dial `to` without an address lookup service.
+pub(crate) fn learn(from: &Endpoint, to: &Endpoint) -> anyhow::Result<()> {
+    let lookup = MemoryLookup::new();
+    lookup.add_endpoint_info(to.addr());
+    from.address_lookup()?.add(lookup);
+    Ok(())
+}
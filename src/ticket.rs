@@ -0,0 +1,120 @@
+use std::{fmt, str::FromStr};
+
+use iroh::EndpointAddr;
+use serde::{Deserialize, Serialize};
+
+use crate::topic::TopicScheme;
+
+/// Prefix of the string form, so tickets are recognizable when pasted.
+const TICKET_PREFIX: &str = "topic";
+
+/// Max bootstrap addresses embedded in a ticket.
+pub const MAX_TICKET_BOOTSTRAP: usize = 8;
+
+/// Invitation to a topic: topic key, optional secret and namespace, and a few
+/// peers to dial before the DHT lookup finishes.
+///
+/// The string form is `topic` followed by the base32 encoded postcard bytes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TopicTicket {
+    topic: Vec<u8>,
+    secret: Option<[u8; 32]>,
+    namespace: Option<Vec<u8>>,
+    bootstrap: Vec<EndpointAddr>,
+}
+
+/// Versioned wire format of a ticket.
+#[derive(Serialize, Deserialize)]
+enum TicketWire {
+    V1(TopicTicket),
+}
+
+impl TopicTicket {
+    pub fn new(topic: impl Into<Vec<u8>>) -> Self {
+        Self {
+            topic: topic.into(),
+            secret: None,
+            namespace: None,
+            bootstrap: Vec::new(),
+        }
+    }
+
+    /// Topic only findable by holders of the ticket, see `TopicScheme::with_secret`.
+    pub fn with_secret(mut self, secret: [u8; 32]) -> Self {
+        self.secret = Some(secret);
+        self
+    }
+
+    /// Ticket for a new private topic with a random secret.
+    pub fn generate_secret(self) -> Self {
+        self.with_secret(rand::random())
+    }
+
+    pub fn with_namespace(mut self, namespace: impl Into<Vec<u8>>) -> Self {
+        self.namespace = Some(namespace.into());
+        self
+    }
+
+    /// Peers to dial first, usually the inviting endpoint's `endpoint.addr()`.
+    /// Only the first `MAX_TICKET_BOOTSTRAP` are kept.
+    pub fn with_bootstrap(mut self, bootstrap: impl IntoIterator<Item = EndpointAddr>) -> Self {
+        self.bootstrap = bootstrap.into_iter().take(MAX_TICKET_BOOTSTRAP).collect();
+        self
+    }
+
+    pub fn topic(&self) -> &[u8] {
+        &self.topic
+    }
+
+    pub fn secret(&self) -> Option<&[u8; 32]> {
+        self.secret.as_ref()
+    }
+
+    pub fn namespace(&self) -> Option<&[u8]> {
+        self.namespace.as_deref()
+    }
+
+    pub fn bootstrap(&self) -> &[EndpointAddr] {
+        &self.bootstrap
+    }
+
+    /// `base` with the ticket's namespace and secret, keeping its domain separator.
+    pub fn topic_scheme(&self, base: &TopicScheme) -> TopicScheme {
+        let mut scheme = TopicScheme::custom(base.domain());
+        if let Some(namespace) = &self.namespace {
+            scheme = scheme.with_namespace(namespace.clone());
+        }
+        if let Some(secret) = self.secret {
+            scheme = scheme.with_secret(secret);
+        }
+        scheme
+    }
+}
+
+impl fmt::Display for TopicTicket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes = postcard::to_stdvec(&TicketWire::V1(self.clone())).map_err(|_| fmt::Error)?;
+        let encoded = data_encoding::BASE32_NOPAD
+            .encode(&bytes)
+            .to_ascii_lowercase();
+        write!(f, "{TICKET_PREFIX}{encoded}")
+    }
+}
+
+impl FromStr for TopicTicket {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let encoded = s
+            .trim()
+            .strip_prefix(TICKET_PREFIX)
+            .ok_or_else(|| anyhow::anyhow!("not a topic ticket"))?;
+        let bytes = data_encoding::BASE32_NOPAD.decode(encoded.to_ascii_uppercase().as_bytes())?;
+        let TicketWire::V1(ticket) = postcard::from_bytes(&bytes)?;
+        anyhow::ensure!(
+            ticket.bootstrap.len() <= MAX_TICKET_BOOTSTRAP,
+            "ticket has too many bootstrap peers"
+        );
+        Ok(ticket)
+    }
+}
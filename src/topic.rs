@@ -0,0 +1,114 @@
+use sha2::Digest;
+
+/// Domain separator used by the v2 topic hash (the scheme shipped in 0.2).
+pub const TOPIC_DOMAIN_V2: &str = "/iroh/topic-discovery/v2";
+
+/// How raw topic bytes are turned into the gossip `TopicId` and the DHT infohash.
+///
+/// The default is the v2 scheme without a namespace, which is bit-for-bit what
+/// 0.2 clients derive. Apps should set a namespace so that two unrelated apps
+/// picking the same topic name end up in different swarms.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopicScheme {
+    domain: String,
+    namespace: Option<Vec<u8>>,
+    secret: Option<[u8; 32]>,
+}
+
+impl Default for TopicScheme {
+    fn default() -> Self {
+        Self::v2()
+    }
+}
+
+impl TopicScheme {
+    pub fn v2() -> Self {
+        Self::custom(TOPIC_DOMAIN_V2)
+    }
+
+    /// Scheme with a custom domain separator, e.g. for a future hash change.
+    pub fn custom(domain: impl Into<String>) -> Self {
+        Self {
+            domain: domain.into(),
+            namespace: None,
+            secret: None,
+        }
+    }
+
+    /// Fold an application namespace into the topic derivation.
+    pub fn with_namespace(mut self, namespace: impl Into<Vec<u8>>) -> Self {
+        self.namespace = Some(namespace.into());
+        self
+    }
+
+    /// Fold a shared secret into the topic derivation, so only holders of the
+    /// secret can find the topic on the DHT.
+    pub fn with_secret(mut self, secret: [u8; 32]) -> Self {
+        self.secret = Some(secret);
+        self
+    }
+
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    pub fn namespace(&self) -> Option<&[u8]> {
+        self.namespace.as_deref()
+    }
+
+    pub fn secret(&self) -> Option<&[u8; 32]> {
+        self.secret.as_ref()
+    }
+
+    /// 32 byte topic hash, used as gossip `TopicId`.
+    pub fn topic_hash_32(&self, topic_bytes: &[u8]) -> [u8; 32] {
+        let mut hasher = sha2::Sha512::new();
+        hasher.update(self.domain.as_bytes());
+        // length prefixed so namespace and topic can't be shifted into each other
+        if let Some(namespace) = &self.namespace {
+            hasher.update((namespace.len() as u64).to_be_bytes());
+            hasher.update(namespace);
+        }
+        if let Some(secret) = &self.secret {
+            hasher.update(b"/secret");
+            hasher.update(secret);
+        }
+        hasher.update(topic_bytes);
+        hasher.finalize()[..32].try_into().expect("hashing failed")
+    }
+
+    /// 20 byte DHT infohash of a topic, also used to match LAN announcements.
+    pub fn topic_infohash(&self, topic_bytes: &[u8]) -> [u8; 20] {
+        topic_hash_20(&self.topic_hash_32(topic_bytes))
+    }
+}
+
+pub(crate) fn topic_hash_20(topic_hash_32: &[u8; 32]) -> [u8; 20] {
+    let mut hasher = sha2::Sha512::new();
+    hasher.update(topic_hash_32);
+    hasher.finalize()[..20].try_into().expect("hashing failed")
+}
+
+/// DHT infohash for a 32 byte topic hash.
+pub(crate) fn topic_dht_id(topic_hash_32: &[u8; 32]) -> anyhow::Result<dht::Id> {
+    Ok(dht::Id::from_bytes(topic_hash_20(topic_hash_32))?)
+}
+
+/// Infohash of `shard` out of `shards` for a topic infohash. With one shard this
+/// is the topic infohash itself, so unsharded topics stay compatible.
+pub fn shard_infohash(infohash: &[u8; 20], shard: u16, shards: u16) -> [u8; 20] {
+    if shards <= 1 {
+        return *infohash;
+    }
+    let mut hasher = sha2::Sha512::new();
+    hasher.update(infohash);
+    hasher.update(b"/shard");
+    hasher.update(shards.to_be_bytes());
+    hasher.update(shard.to_be_bytes());
+    hasher.finalize()[..20].try_into().expect("hashing failed")
+}
+
+/// Shard a node announces into, by the prefix of its public key.
+pub fn shard_for_key(key: &[u8; 32], shards: u16) -> u16 {
+    u16::from_be_bytes([key[0], key[1]]) % shards.max(1)
+}
@@ -131,3 +131,569 @@ async fn topic_tracker_gossip_integration_test() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn topic_scheme_namespace_separates_topics() {
+    use iroh_topic_tracker::TopicScheme;
+
+    let plain = TopicScheme::v2();
+    let app_a = TopicScheme::v2().with_namespace("app-a");
+    let app_b = TopicScheme::v2().with_namespace("app-b");
+
+    assert_eq!(
+        plain.topic_hash_32(b"chat"),
+        TopicScheme::default().topic_hash_32(b"chat")
+    );
+    assert_ne!(plain.topic_hash_32(b"chat"), app_a.topic_hash_32(b"chat"));
+    assert_ne!(app_a.topic_hash_32(b"chat"), app_b.topic_hash_32(b"chat"));
+    // namespace and topic are length prefixed and can't be shifted into each other
+    assert_ne!(
+        TopicScheme::v2().with_namespace("ab").topic_hash_32(b"c"),
+        TopicScheme::v2().with_namespace("a").topic_hash_32(b"bc")
+    );
+}
+
+#[test]
+fn peer_selectors_respect_budget_and_order() {
+    use iroh_topic_tracker::{NewestSelector, PeerCandidate, PeerSelector, RoundRobinSelector};
+
+    let candidates = (0..4u64)
+        .map(|timestamp| PeerCandidate {
+            peer: SecretKey::generate().public(),
+            timestamp,
+        })
+        .collect::<Vec<_>>();
+
+    let newest = NewestSelector.select(candidates.clone(), 2);
+    assert_eq!(newest, vec![candidates[3], candidates[2]]);
+
+    // round robin hands out every candidate once before repeating
+    let round_robin = RoundRobinSelector::default();
+    let first = round_robin.select(candidates.clone(), 2);
+    let second = round_robin.select(candidates.clone(), 2);
+    assert_eq!(first, vec![candidates[3], candidates[2]]);
+    assert_eq!(second, vec![candidates[1], candidates[0]]);
+}
+
+#[test]
+fn staleness_policy_rejects_old_and_far_future_records() {
+    use iroh_topic_tracker::{RecordFreshness, StalenessPolicy};
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_micros() as u64;
+    let minute = 60_000_000;
+    let policy = StalenessPolicy {
+        max_age: Some(Duration::from_secs(600)),
+        ..Default::default()
+    };
+    let fallback_max_age = Duration::from_secs(60);
+
+    assert_eq!(
+        policy.classify(now, fallback_max_age),
+        RecordFreshness::Fresh
+    );
+    // max_age overrides our own announce interval based default
+    assert_eq!(
+        policy.classify(now - 5 * minute, fallback_max_age),
+        RecordFreshness::Fresh
+    );
+    assert_eq!(
+        policy.classify(now - 11 * minute, fallback_max_age),
+        RecordFreshness::Stale
+    );
+    assert_eq!(
+        policy.classify(now + 10_000_000, fallback_max_age),
+        RecordFreshness::Fresh
+    );
+    assert_eq!(
+        policy.classify(now + minute, fallback_max_age),
+        RecordFreshness::FromFuture
+    );
+}
+
+#[test]
+fn metrics_render_prometheus_text() {
+    use iroh_topic_tracker::DiscoveryMetrics;
+
+    let metrics = DiscoveryMetrics {
+        announces_ok: 3,
+        announces_failed: 1,
+        connector_attempts: 2,
+        connector_successes: 1,
+        ..Default::default()
+    };
+    let text = metrics.to_prometheus(&[("topic", "chat")]);
+
+    assert_eq!(metrics.announce_success_rate(), Some(0.75));
+    assert!(text.contains("# TYPE iroh_topic_tracker_announces_total counter"));
+    assert!(text.contains("iroh_topic_tracker_announces_total{topic=\"chat\",result=\"ok\"} 3"));
+    assert!(text.contains("iroh_topic_tracker_connector_attempts_total{topic=\"chat\"} 2"));
+    assert!(
+        text.contains("iroh_topic_tracker_connector_total{topic=\"chat\",result=\"success\"} 1")
+    );
+    assert!(!text.contains("result=\"attempt\""));
+    assert!(!text.contains("time_to_first_peer"));
+}
+
+#[tokio::test]
+async fn lan_discovery_receives_signed_announcements() -> anyhow::Result<()> {
+    use iroh_topic_tracker::{LanDiscovery, LanDiscoveryConfig, TopicScheme};
+
+    let config = LanDiscoveryConfig {
+        group: "239.255.77.78:7748".parse()?,
+        ..Default::default()
+    };
+    let alice_key = ed25519_dalek::SigningKey::from_bytes(&rand::random());
+    let alice = LanDiscovery::bind(config.clone(), alice_key.clone())?;
+    let bob = LanDiscovery::bind(
+        config,
+        ed25519_dalek::SigningKey::from_bytes(&rand::random()),
+    )?;
+
+    let infohash = TopicScheme::v2().topic_infohash(b"lan-test");
+    let direct_addrs = vec!["192.168.1.2:4433".parse()?];
+    alice.announce(infohash, direct_addrs.clone()).await?;
+
+    let announcement = timeout(Duration::from_secs(5), bob.recv()).await??;
+    assert_eq!(announcement.infohash, infohash);
+    assert_eq!(
+        announcement.endpoint_id.as_bytes(),
+        alice_key.verifying_key().as_bytes()
+    );
+    assert_eq!(announcement.direct_addrs, direct_addrs);
+    Ok(())
+}
+
+#[test]
+fn peer_cache_persists_connected_peers() -> anyhow::Result<()> {
+    use iroh_topic_tracker::{DiscoveredPeer, DiscoverySource, PeerCache, SourceKind};
+
+    let path = std::env::temp_dir().join(format!("peer_cache_{}.json", rand::random::<u32>()));
+    let peer = SecretKey::generate().public();
+    let direct_addrs = vec!["10.0.0.1:4433".parse()?];
+
+    let cache = PeerCache::open(&path, 8)?;
+    assert!(cache.peers().is_empty());
+    cache.peer_connected(&DiscoveredPeer::new(peer).with_direct_addrs(direct_addrs.clone()));
+    // a later connection without addresses keeps the known ones
+    cache.peer_connected(&DiscoveredPeer::new(peer));
+
+    let reopened = PeerCache::open(&path, 8)?;
+    assert_eq!(
+        reopened.peers(),
+        vec![DiscoveredPeer::new(peer).with_direct_addrs(direct_addrs)]
+    );
+    assert!(SourceKind::Lan.default_policy().priority > SourceKind::Dht.default_policy().priority);
+
+    std::fs::remove_file(path)?;
+    Ok(())
+}
+
+#[test]
+fn topic_ticket_roundtrips_through_string() -> anyhow::Result<()> {
+    use iroh::{EndpointAddr, TransportAddr};
+    use iroh_topic_tracker::{TopicScheme, TopicTicket};
+
+    let peer = SecretKey::generate().public();
+    let addr = EndpointAddr::from_parts(peer, [TransportAddr::Ip("192.168.1.2:4433".parse()?)]);
+    let ticket = TopicTicket::new("chat")
+        .with_namespace("com.example.app")
+        .generate_secret()
+        .with_bootstrap([addr]);
+
+    let encoded = ticket.to_string();
+    assert!(encoded.starts_with("topic"));
+    let decoded: TopicTicket = encoded.parse()?;
+    assert_eq!(decoded, ticket);
+
+    // the secret changes the topic, so the plain namespace can't find it
+    let scheme = decoded.topic_scheme(&TopicScheme::v2());
+    assert_ne!(
+        scheme.topic_hash_32(b"chat"),
+        TopicScheme::v2()
+            .with_namespace("com.example.app")
+            .topic_hash_32(b"chat")
+    );
+    assert!("topicnotbase32!".parse::<TopicTicket>().is_err());
+    Ok(())
+}
+
+#[test]
+fn membership_capabilities_are_bound_to_topic_member_and_expiry() -> anyhow::Result<()> {
+    use iroh_topic_tracker::{MembershipCapability, TopicScheme};
+    use std::time::SystemTime;
+
+    let owner = SecretKey::generate();
+    let owner_key = ed25519_dalek::SigningKey::from_bytes(&owner.to_bytes());
+    let member = SecretKey::generate().public();
+    let infohash = TopicScheme::v2().topic_infohash(b"team");
+    let other_topic = TopicScheme::v2().topic_infohash(b"other");
+    let in_an_hour = SystemTime::now() + Duration::from_secs(3600);
+
+    let capability = MembershipCapability::issue(&owner_key, infohash, member, in_an_hour)?;
+    capability.verify(&owner.public(), &infohash)?;
+    assert!(capability.verify(&owner.public(), &other_topic).is_err());
+    assert!(
+        capability
+            .verify(&SecretKey::generate().public(), &infohash)
+            .is_err()
+    );
+
+    let expired = MembershipCapability::issue(
+        &owner_key,
+        infohash,
+        member,
+        SystemTime::now() - Duration::from_secs(1),
+    )?;
+    assert!(expired.verify(&owner.public(), &infohash).is_err());
+    Ok(())
+}
+
+#[test]
+fn proof_of_work_is_bound_to_endpoint_and_topic() {
+    use iroh_topic_tracker::{ProofOfWork, TopicScheme};
+
+    let endpoint = SecretKey::generate().public();
+    let infohash = TopicScheme::v2().topic_infohash(b"chat");
+
+    let proof = ProofOfWork::solve(&infohash, &endpoint, 8);
+    assert!(proof.verify(&infohash, &endpoint, 8));
+    assert!(proof.difficulty(&infohash, &endpoint) >= 8);
+    // a 64 bit proof is out of reach for a nonce found at difficulty 8
+    assert!(!proof.verify(&infohash, &endpoint, 64));
+}
+
+#[test]
+fn shard_infohashes_keep_unsharded_topics_compatible() {
+    use iroh_topic_tracker::{TopicScheme, shard_for_key, shard_infohash};
+
+    let infohash = TopicScheme::v2().topic_infohash(b"big-topic");
+    assert_eq!(shard_infohash(&infohash, 0, 1), infohash);
+
+    let shards = (0..8)
+        .map(|shard| shard_infohash(&infohash, shard, 8))
+        .collect::<std::collections::HashSet<_>>();
+    assert_eq!(shards.len(), 8);
+    assert!(!shards.contains(&infohash));
+
+    let key = *SecretKey::generate().public().as_bytes();
+    assert!(shard_for_key(&key, 8) < 8);
+    assert_eq!(shard_for_key(&key, 1), 0);
+}
+
+#[test]
+fn adaptive_announce_mode_scales_with_visible_records() {
+    use iroh_topic_tracker::AnnounceMode;
+
+    let adaptive = AnnounceMode::Adaptive { target_records: 20 };
+    assert_eq!(AnnounceMode::Always.announce_probability(Some(1000)), 1.0);
+    assert_eq!(adaptive.announce_probability(None), 1.0);
+    assert_eq!(adaptive.announce_probability(Some(5)), 1.0);
+    assert_eq!(adaptive.announce_probability(Some(200)), 0.1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn compat_scheme_joins_legacy_peers_into_their_topic() -> anyhow::Result<()> {
+    use iroh_topic_tracker::{LanDiscoveryConfig, TopicScheme};
+
+    async fn lan_node(
+        scheme: TopicScheme,
+        compat: Vec<TopicScheme>,
+    ) -> anyhow::Result<(Gossip, Router, TopicDiscoveryConfig)> {
+        let endpoint = Endpoint::builder(iroh::endpoint::presets::Minimal)
+            .bind()
+            .await?;
+        let gossip = Gossip::builder().spawn(endpoint.clone());
+        let router = Router::builder(endpoint.clone())
+            .accept(iroh_gossip::ALPN, gossip.clone())
+            .spawn();
+        let config = TopicDiscoveryConfig::builder(endpoint)
+            .topic_scheme(scheme)
+            .compat_topic_schemes(compat)
+            .dht_retries(Some(0))
+            .lan_discovery(Some(LanDiscoveryConfig {
+                group: "239.255.77.79:7749".parse()?,
+                announce_interval: Duration::from_secs(1),
+                ..Default::default()
+            }))
+            .build();
+        Ok((gossip, router, config))
+    }
+
+    async fn receive(receiver: &mut iroh_gossip::api::GossipReceiver, expected: &[u8]) -> bool {
+        while let Some(Ok(event)) = receiver.next().await {
+            if let Event::Received(msg) = event
+                && msg.content.as_ref() == expected
+            {
+                return true;
+            }
+        }
+        false
+    }
+
+    let topic = format!("compat_{}", rand::random::<u32>()).into_bytes();
+    let (legacy_gossip, _legacy_router, legacy_config) =
+        lan_node(TopicScheme::v2(), Vec::new()).await?;
+    let (gossip, _router, config) = lan_node(
+        TopicScheme::v2().with_namespace("app"),
+        vec![TopicScheme::v2()],
+    )
+    .await?;
+
+    let (legacy_sender, mut legacy_receiver, _legacy_handle) = legacy_gossip
+        .subscribe_with_discovery(topic.clone(), vec![], legacy_config)
+        .await?;
+    let (_sender, mut receiver, handle) = gossip
+        .subscribe_with_discovery(topic, vec![], config)
+        .await?;
+    timeout(Duration::from_secs(20), legacy_receiver.joined()).await??;
+
+    // drained until taken, the receiver still tracks the neighbors that came up meanwhile
+    let mut compat_topics = handle.take_compat_topics().await;
+    assert_eq!(compat_topics.len(), 1);
+    assert!(handle.take_compat_topics().await.is_empty());
+    let (compat_sender, mut compat_receiver) = compat_topics.remove(0);
+    timeout(Duration::from_secs(10), compat_receiver.joined()).await??;
+
+    legacy_sender
+        .broadcast(b"from legacy".to_vec().into())
+        .await?;
+    assert!(
+        timeout(
+            Duration::from_secs(10),
+            receive(&mut compat_receiver, b"from legacy")
+        )
+        .await?
+    );
+    compat_sender
+        .broadcast(b"from current".to_vec().into())
+        .await?;
+    assert!(
+        timeout(
+            Duration::from_secs(10),
+            receive(&mut legacy_receiver, b"from current")
+        )
+        .await?
+    );
+
+    // the legacy peer never subscribed to the namespaced topic and isn't joined into it
+    assert!(
+        timeout(Duration::from_secs(2), receiver.joined())
+            .await
+            .is_err()
+    );
+    Ok(())
+}
+
+#[test]
+fn timings_jitter_is_bounded_and_ignores_nan() {
+    use iroh_topic_tracker::Timings;
+
+    let base = Duration::from_secs(10);
+    let nan = Timings {
+        jitter: f64::NAN,
+        ..Default::default()
+    };
+    assert_eq!(nan.jittered(base), base);
+
+    let wide = Timings {
+        jitter: 5.0,
+        ..Default::default()
+    };
+    for _ in 0..100 {
+        assert!(wide.jittered(base) < base * 2);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ticket_subscription_does_not_wait_for_the_dht() -> anyhow::Result<()> {
+    use iroh_topic_tracker::TopicTicket;
+
+    let peer = Endpoint::builder(iroh::endpoint::presets::Minimal)
+        .bind()
+        .await?;
+    let endpoint = Endpoint::builder(iroh::endpoint::presets::Minimal)
+        .bind()
+        .await?;
+    let gossip = Gossip::builder().spawn(endpoint.clone());
+    let _router = Router::builder(endpoint.clone())
+        .accept(iroh_gossip::ALPN, gossip.clone())
+        .spawn();
+
+    let ticket = TopicTicket::new("ticket").with_bootstrap([peer.addr()]);
+    let config = TopicDiscoveryConfig::builder(endpoint).build();
+    let (_sender, _receiver, handle) = timeout(
+        Duration::from_secs(2),
+        gossip.subscribe_with_ticket(&ticket, config),
+    )
+    .await??;
+    handle.stop();
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bootstrap_nodes_accept_addrs_and_bare_ids() -> anyhow::Result<()> {
+    use iroh_topic_tracker::LanDiscoveryConfig;
+
+    // separate LAN groups, the nodes only meet through the bootstrap address
+    async fn node(port: u16) -> anyhow::Result<(Endpoint, Gossip, Router, TopicDiscoveryConfig)> {
+        let endpoint = Endpoint::builder(iroh::endpoint::presets::Minimal)
+            .bind()
+            .await?;
+        let gossip = Gossip::builder().spawn(endpoint.clone());
+        let router = Router::builder(endpoint.clone())
+            .accept(iroh_gossip::ALPN, gossip.clone())
+            .spawn();
+        let config = TopicDiscoveryConfig::builder(endpoint.clone())
+            .dht_retries(Some(0))
+            .lan_discovery(Some(LanDiscoveryConfig {
+                group: format!("239.255.77.80:{port}").parse()?,
+                ..Default::default()
+            }))
+            .build();
+        Ok((endpoint, gossip, router, config))
+    }
+
+    let topic = format!("bootstrap_{}", rand::random::<u32>()).into_bytes();
+    let (endpoint0, gossip0, _router0, config0) = node(7751).await?;
+    let (_endpoint1, gossip1, _router1, config1) = node(7752).await?;
+    let (endpoint2, gossip2, _router2, config2) = node(7753).await?;
+
+    let (_sender0, _receiver0, _handle0) = gossip0
+        .subscribe_with_discovery(topic.clone(), vec![], config0)
+        .await?;
+    let (_sender1, mut receiver1, _handle1) = gossip1
+        .subscribe_with_discovery_with_addrs(topic.clone(), vec![endpoint0.addr()], config1)
+        .await?;
+    timeout(Duration::from_secs(10), receiver1.joined()).await??;
+
+    // a bare id is resolved through the endpoint's address lookup
+    let lookup = iroh::address_lookup::MemoryLookup::new();
+    lookup.add_endpoint_info(endpoint0.addr());
+    endpoint2.address_lookup()?.add(lookup);
+    let (_sender2, mut receiver2, _handle2) = gossip2
+        .subscribe_with_discovery(topic, vec![endpoint0.id()], config2)
+        .await?;
+    timeout(Duration::from_secs(10), receiver2.joined()).await??;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn membership_must_match_the_subscribed_topic() -> anyhow::Result<()> {
+    use iroh_topic_tracker::{LanDiscoveryConfig, Membership, TopicScheme};
+
+    let endpoint = Endpoint::builder(iroh::endpoint::presets::Minimal)
+        .bind()
+        .await?;
+    let gossip = Gossip::builder().spawn(endpoint.clone());
+    let config = |infohash| {
+        TopicDiscoveryConfig::builder(endpoint.clone())
+            .dht_retries(Some(0))
+            .lan_discovery(Some(LanDiscoveryConfig {
+                group: "239.255.77.81:7754".parse().expect("valid group"),
+                ..Default::default()
+            }))
+            .membership(Some(Membership::open(infohash)))
+            .build()
+    };
+
+    let other = TopicScheme::v2().topic_infohash(b"other");
+    assert!(
+        gossip
+            .subscribe_with_discovery(b"chat".to_vec(), vec![], config(other))
+            .await
+            .is_err()
+    );
+    let chat = TopicScheme::v2().topic_infohash(b"chat");
+    let (_sender, _receiver, handle) = gossip
+        .subscribe_with_discovery(b"chat".to_vec(), vec![], config(chat))
+        .await?;
+    handle.stop();
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn attach_discovery_joins_peers_into_the_existing_subscription() -> anyhow::Result<()> {
+    use iroh_gossip::proto::TopicId;
+    use iroh_topic_tracker::{LanDiscoveryConfig, TopicScheme};
+
+    async fn node() -> anyhow::Result<(Gossip, Router, TopicDiscoveryConfig)> {
+        let endpoint = Endpoint::builder(iroh::endpoint::presets::Minimal)
+            .bind()
+            .await?;
+        let gossip = Gossip::builder().spawn(endpoint.clone());
+        let router = Router::builder(endpoint.clone())
+            .accept(iroh_gossip::ALPN, gossip.clone())
+            .spawn();
+        let config = TopicDiscoveryConfig::builder(endpoint)
+            .dht_retries(Some(0))
+            .lan_discovery(Some(LanDiscoveryConfig {
+                group: "239.255.77.82:7755".parse()?,
+                announce_interval: Duration::from_secs(1),
+                ..Default::default()
+            }))
+            .build();
+        Ok((gossip, router, config))
+    }
+
+    let topic = format!("attach_{}", rand::random::<u32>()).into_bytes();
+    let (gossip0, _router0, config0) = node().await?;
+    let (gossip1, _router1, config1) = node().await?;
+
+    // subscribed by the application, discovery only finds the peers
+    let topic_id = TopicId::from_bytes(TopicScheme::v2().topic_hash_32(&topic));
+    let (sender0, mut receiver0) = gossip0.subscribe(topic_id, vec![]).await?.split();
+    let handle0 = gossip0
+        .attach_discovery(topic_id, sender0.clone(), config0)
+        .await?;
+    let (_sender1, mut receiver1, _handle1) = gossip1
+        .subscribe_with_discovery(topic, vec![], config1)
+        .await?;
+
+    timeout(Duration::from_secs(20), receiver0.joined()).await??;
+    timeout(Duration::from_secs(20), handle0.wait_for_connections(1)).await?;
+
+    sender0.broadcast(b"attached".to_vec().into()).await?;
+    let received = timeout(Duration::from_secs(10), async {
+        while let Some(Ok(event)) = receiver1.next().await {
+            if let Event::Received(msg) = event {
+                return msg.content.as_ref() == b"attached";
+            }
+        }
+        false
+    })
+    .await?;
+    assert!(received);
+    Ok(())
+}
+
+#[tokio::test]
+async fn joined_timeout_reports_how_far_the_join_got() -> anyhow::Result<()> {
+    use iroh_topic_tracker::JoinTimeoutError;
+
+    let endpoint = Endpoint::builder(iroh::endpoint::presets::Minimal)
+        .bind()
+        .await?;
+    let gossip = Gossip::builder().spawn(endpoint.clone());
+    let config = TopicDiscoveryConfig::builder(endpoint).build();
+
+    // nobody else is on the topic
+    let topic = format!("lonely_{}", rand::random::<u32>()).into_bytes();
+    let started = tokio::time::Instant::now();
+    let err = gossip
+        .subscribe_with_discovery_joined_timeout(topic, vec![], config, 1, Duration::from_secs(2))
+        .await
+        .expect_err("no peer can join");
+
+    let timed_out = err
+        .downcast_ref::<JoinTimeoutError>()
+        .expect("a JoinTimeoutError");
+    assert!(!timed_out.gossip_joined);
+    assert_eq!(timed_out.connected, 0);
+    assert_eq!(timed_out.required, 1);
+    assert!(timed_out.elapsed >= Duration::from_secs(2));
+    assert!(started.elapsed() < Duration::from_secs(10));
+    Ok(())
+}